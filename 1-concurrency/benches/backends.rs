@@ -0,0 +1,81 @@
+//! Throughput of the three backends on synthetic trees. Run with `cargo bench`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mygrep::matcher::{self, MatchOptions};
+use mygrep::{GrepResult, Kind, Searcher, Sink};
+use regex::bytes::Regex;
+
+const KINDS: [(&str, Kind); 3] = [
+    ("self-made", Kind::SelfMade),
+    ("rayon", Kind::Rayon),
+    ("tokio", Kind::Tokio),
+];
+
+/// Throws results away, so only the search itself is measured.
+struct Discard;
+
+impl Sink for Discard {
+    fn result(&mut self, _: GrepResult) {}
+}
+
+fn bench_tree(c: &mut Criterion, name: &str, tree: common::Tree) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(tree.bytes));
+    group.sample_size(10);
+    let roots = [tree.path().to_path_buf()];
+    let regex = Regex::new(common::NEEDLE).unwrap();
+    for (kind_name, kind) in KINDS {
+        let searcher = Searcher::builder(regex.clone()).kind(kind).build();
+        group.bench_function(BenchmarkId::from_parameter(kind_name), |b| {
+            b.iter(|| searcher.run(&roots, Discard).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn backends(c: &mut Criterion) {
+    bench_tree(
+        c,
+        "many_small",
+        common::many_small(10_000, 4 * 1024).unwrap(),
+    );
+    bench_tree(
+        c,
+        "few_huge",
+        common::few_huge(4, 64 * 1024 * 1024).unwrap(),
+    );
+    bench_tree(c, "deep", common::deep(200, 8, 4 * 1024).unwrap());
+}
+
+/// A pattern that doesn't start with a literal, but has one in the middle.
+const INNER_LITERAL: &str = r"[a-z]+ index_\d+ fn";
+
+/// The same search with and without the literal prefilter, on every backend.
+fn prefilter(c: &mut Criterion) {
+    let tree = common::few_huge(2, 64 * 1024 * 1024).unwrap();
+    let mut group = c.benchmark_group("prefilter");
+    group.throughput(Throughput::Bytes(tree.bytes));
+    group.sample_size(10);
+    let roots = [tree.path().to_path_buf()];
+    for (kind_name, kind) in KINDS {
+        for no_prefilter in [true, false] {
+            let options = MatchOptions {
+                no_prefilter,
+                ..MatchOptions::default()
+            };
+            let matcher = matcher::build(&[INNER_LITERAL.to_string()], &options).unwrap();
+            let searcher = Searcher::builder(matcher).kind(kind).build();
+            let variant = if no_prefilter { "regex" } else { "prefilter" };
+            group.bench_function(BenchmarkId::new(kind_name, variant), |b| {
+                b.iter(|| searcher.run(&roots, Discard).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, backends, prefilter);
+criterion_main!(benches);
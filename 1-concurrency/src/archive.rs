@@ -0,0 +1,172 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// The archive formats `--search-zip` looks inside, recognized by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `.zip` and `.jar`
+    Zip,
+    Tar,
+    /// `.tar.gz` and `.tgz`
+    TarGz,
+}
+
+impl Format {
+    /// The format of `path`, or `None` if it doesn't look like an archive.
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (_, extension) = name.rsplit_once('.')?;
+        match extension {
+            "zip" | "jar" => Some(Self::Zip),
+            "tar" => Some(Self::Tar),
+            "tgz" => Some(Self::TarGz),
+            "gz" if name.ends_with(".tar.gz") => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+
+    /// Call `f` with the virtual path and the contents of every regular file in the archive
+    /// at `path`, in the order they are stored in. Stops early when `f` returns false.
+    /// Fails if the archive itself can't be read, errors in single entries go to `f`.
+    pub fn for_each_entry(
+        self,
+        path: &Path,
+        mut f: impl FnMut(PathBuf, io::Result<Vec<u8>>) -> bool,
+    ) -> io::Result<()> {
+        let file = File::open(path)?;
+        match self {
+            Self::Zip => {
+                let mut zip = zip::ZipArchive::new(BufReader::new(file))?;
+                for i in 0..zip.len() {
+                    let mut entry = zip.by_index(i)?;
+                    if !entry.is_file() {
+                        continue;
+                    }
+                    let inner = entry_path(path, entry.name()?.as_ref());
+                    if !f(inner, read_entry(&mut entry)) {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            Self::Tar => for_each_tar_entry(path, BufReader::new(file), f),
+            Self::TarGz => for_each_tar_entry(
+                path,
+                flate2::bufread::MultiGzDecoder::new(BufReader::new(file)),
+                f,
+            ),
+        }
+    }
+}
+
+fn for_each_tar_entry(
+    path: &Path,
+    reader: impl Read,
+    mut f: impl FnMut(PathBuf, io::Result<Vec<u8>>) -> bool,
+) -> io::Result<()> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let inner = entry_path(path, &entry.path()?.to_string_lossy());
+        if !f(inner, read_entry(&mut entry)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_entry(entry: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    entry.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// The path an entry is reported under: `archive.tar!/path/inside`. Leading `/` and `./`,
+/// which tar archives often store their paths with, are left out.
+fn entry_path(archive: &Path, mut inner: &str) -> PathBuf {
+    loop {
+        inner = inner.trim_start_matches('/');
+        match inner.strip_prefix("./") {
+            Some(rest) => inner = rest,
+            None => break,
+        }
+    }
+    let mut path = OsString::from(archive);
+    path.push("!/");
+    path.push(inner);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `path` as a string. Comparing paths would ignore a `.` component.
+    fn string(path: PathBuf) -> String {
+        path.into_os_string().into_string().unwrap()
+    }
+
+    #[test]
+    fn entry_paths_leave_out_leading_slashes_and_dots() {
+        let archive = Path::new("dir/a.tar");
+        for inner in [
+            "src/lib.rs",
+            "./src/lib.rs",
+            "/src/lib.rs",
+            ".//./src/lib.rs",
+        ] {
+            let path = string(entry_path(archive, inner));
+            assert_eq!(path, "dir/a.tar!/src/lib.rs", "{:?}", inner);
+        }
+        let path = string(entry_path(archive, ".hidden/x"));
+        assert_eq!(path, "dir/a.tar!/.hidden/x");
+    }
+
+    #[test]
+    fn formats_are_recognized_by_extension() {
+        assert_eq!(Format::of(Path::new("a.zip")), Some(Format::Zip));
+        assert_eq!(Format::of(Path::new("a.jar")), Some(Format::Zip));
+        assert_eq!(Format::of(Path::new("a.tar")), Some(Format::Tar));
+        assert_eq!(Format::of(Path::new("a.tar.gz")), Some(Format::TarGz));
+        assert_eq!(Format::of(Path::new("a.tgz")), Some(Format::TarGz));
+        assert_eq!(Format::of(Path::new("a.gz")), None);
+        assert_eq!(Format::of(Path::new("tar")), None);
+    }
+
+    #[test]
+    fn tar_entries_stored_with_a_leading_dot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for name in ["./one.txt", "two.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            builder.append(&header, &b"hit\n"[..]).unwrap();
+        }
+        builder.finish().unwrap();
+
+        let mut entries = Vec::new();
+        Format::Tar
+            .for_each_entry(&path, |inner, content| {
+                entries.push((string(inner), content.unwrap()));
+                true
+            })
+            .unwrap();
+        let archive = path.display();
+        assert_eq!(
+            entries,
+            [
+                (format!("{}!/one.txt", archive), b"hit\n".to_vec()),
+                (format!("{}!/two.txt", archive), b"hit\n".to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use crate::budget::Budget;
+use crate::cancel::CancelToken;
+use crate::diagnostics::Diagnostics;
+use crate::matcher::Matcher;
+use crate::search::SearchOptions;
+use crate::walker::Walker;
+
+pub mod rayon_pool;
+pub mod self_made;
+pub mod tokio_runtime;
+
+/// How many results may wait in the channel between the workers and the printer.
+const RESULT_CHANNEL_CAPACITY: usize = 256;
+
+/// Everything a backend needs to run one search.
+pub struct Job {
+    pub matcher: Arc<dyn Matcher>,
+    pub walker: Walker,
+    pub search: Arc<SearchOptions>,
+    pub unordered: bool,
+    pub budget: Arc<Budget>,
+    pub diagnostics: Arc<Diagnostics>,
+    pub cancel: CancelToken,
+    /// Stop after this many matching lines in total
+    pub max_total: Option<usize>,
+    /// How many files are searched at the same time
+    pub threads: usize,
+}
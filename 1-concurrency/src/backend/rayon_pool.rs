@@ -0,0 +1,62 @@
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::io;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use super::{Job, RESULT_CHANNEL_CAPACITY};
+use crate::grep_result::GrepResult;
+use crate::ordered::{OrderedSink, Producer};
+use crate::search;
+use crate::searcher::Sink;
+
+pub fn run(job: Job, sink: impl Sink + Send + 'static) -> io::Result<()> {
+    let Job {
+        matcher,
+        walker,
+        search,
+        unordered,
+        budget,
+        diagnostics,
+        cancel,
+        max_total,
+        threads,
+    } = job;
+    let producer = Producer::new(Arc::clone(&budget), diagnostics, cancel.clone());
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(io::Error::other)?;
+
+    // The walk runs on its own threads and feeds the pool as it finds files.
+    let (path_tx, path_rx) = mpsc::channel();
+    let walk_handle = thread::spawn(move || {
+        walker.for_each_parallel(|| {
+            let path_tx = path_tx.clone();
+            move |path| {
+                let _ = path_tx.send(path);
+            }
+        });
+    });
+
+    let (res_tx, res_rx) = mpsc::sync_channel::<GrepResult>(RESULT_CHANNEL_CAPACITY);
+    let printer = OrderedSink::new(sink, unordered, budget, cancel, max_total);
+    let printer_handle = thread::spawn(move || printer.run(res_rx));
+
+    pool.install(|| {
+        path_rx.into_iter().par_bridge().for_each(|path| {
+            if producer.is_cancelled() {
+                return;
+            }
+            search::search_each(&*matcher, path, &search, |path, found| {
+                if let Some(result) = producer.result(path, found) {
+                    let _ = res_tx.send(result);
+                }
+            });
+        });
+    });
+    drop(res_tx);
+    let _ = walk_handle.join();
+    let _ = printer_handle.join();
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use super::{Job, RESULT_CHANNEL_CAPACITY};
+use crate::grep_result::GrepResult;
+use crate::ordered::{OrderedSink, Producer};
+use crate::search;
+use crate::searcher::Sink;
+
+pub fn run(job: Job, sink: impl Sink + Send + 'static) {
+    let Job {
+        matcher,
+        walker,
+        search,
+        unordered,
+        budget,
+        diagnostics,
+        cancel,
+        max_total,
+        threads,
+    } = job;
+    let producer = Arc::new(Producer::new(
+        Arc::clone(&budget),
+        diagnostics,
+        cancel.clone(),
+    ));
+
+    let (path_tx, path_rx) = mpsc::channel::<PathBuf>();
+    let (res_tx, res_rx) = mpsc::sync_channel::<GrepResult>(RESULT_CHANNEL_CAPACITY);
+    let path_rx = Arc::new(Mutex::new(path_rx));
+    let printer = OrderedSink::new(sink, unordered, budget, cancel, max_total);
+    let printer_handle = thread::spawn(move || printer.run(res_rx));
+
+    let mut worker_handles = Vec::new();
+    for _ in 0..threads {
+        let path_rx = Arc::clone(&path_rx);
+        let res_tx = res_tx.clone();
+        let matcher = Arc::clone(&matcher);
+        let search = Arc::clone(&search);
+        let producer = Arc::clone(&producer);
+
+        let handle = thread::spawn(move || loop {
+            let path = {
+                let rx_lock = match path_rx.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                match rx_lock.recv() {
+                    Ok(p) => p,
+                    Err(_) => break,
+                }
+            };
+
+            // Keep draining the paths after cancelling, so the walk never blocks on them.
+            if producer.is_cancelled() {
+                continue;
+            }
+            search::search_each(&*matcher, path, &search, |path, found| {
+                if let Some(result) = producer.result(path, found) {
+                    let _ = res_tx.send(result);
+                }
+            });
+        });
+
+        worker_handles.push(handle);
+    }
+    drop(res_tx);
+
+    // Workers pick up files while the walk is still going.
+    walker.for_each_parallel(|| {
+        let path_tx = path_tx.clone();
+        move |path| {
+            let _ = path_tx.send(path);
+        }
+    });
+    drop(path_tx);
+    for handle in worker_handles {
+        let _ = handle.join();
+    }
+    let _ = printer_handle.join();
+}
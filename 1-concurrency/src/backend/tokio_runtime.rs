@@ -0,0 +1,112 @@
+use std::io;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task;
+
+use super::{Job, RESULT_CHANNEL_CAPACITY};
+use crate::grep_result::GrepResult;
+use crate::ordered::{OrderedSink, Producer};
+use crate::search;
+use crate::searcher::Sink;
+
+pub fn run(job: Job, sink: impl Sink + Send + 'static) -> io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(job.threads)
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(run_async(job, sink));
+    Ok(())
+}
+
+async fn run_async(job: Job, sink: impl Sink + Send + 'static) {
+    let Job {
+        matcher,
+        walker,
+        search,
+        unordered,
+        budget,
+        diagnostics,
+        cancel,
+        max_total,
+        threads,
+    } = job;
+    let producer = Arc::new(Producer::new(
+        Arc::clone(&budget),
+        diagnostics,
+        cancel.clone(),
+    ));
+
+    // The walk runs on the blocking pool and feeds paths to the loop below as it finds them.
+    let (path_tx, mut path_rx) = mpsc::unbounded_channel();
+    let walk_handle = task::spawn_blocking(move || {
+        walker.for_each_parallel(|| {
+            let path_tx = path_tx.clone();
+            move |path| {
+                let _ = path_tx.send(path);
+            }
+        });
+    });
+
+    let (res_tx, res_rx) = mpsc::channel::<GrepResult>(RESULT_CHANNEL_CAPACITY);
+    let printer = OrderedSink::new(sink, unordered, budget, cancel, max_total);
+    let printer_handle = task::spawn(printer.run_async(res_rx));
+
+    // At most one file per worker is searched at a time, like in the other backends, so
+    // that files that haven't been started yet are skipped once the search is cancelled.
+    let permits = Arc::new(Semaphore::new(threads));
+
+    let mut handles = Vec::new();
+    while let Some(path) = path_rx.recv().await {
+        if producer.is_cancelled() {
+            continue;
+        }
+        let matcher = Arc::clone(&matcher);
+        let search = Arc::clone(&search);
+        let producer = Arc::clone(&producer);
+        let res_tx = res_tx.clone();
+        let permits = Arc::clone(&permits);
+
+        let handle = task::spawn(async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return;
+            };
+            if producer.is_cancelled() {
+                return;
+            }
+            // Small regular files are read asynchronously, anything that needs mmap,
+            // streaming or decompression goes through the blocking pool.
+            let small = match tokio::fs::metadata(&path).await {
+                Ok(m) => search::is_small_file(&path, &m, &search),
+                Err(_) => false,
+            };
+            if !small {
+                // Archives hand out one result per entry, straight from the blocking pool.
+                let blocking = task::spawn_blocking(move || {
+                    search::search_each(&*matcher, path, &search, |path, found| {
+                        if let Some(result) = producer.result(path, found) {
+                            let _ = res_tx.blocking_send(result);
+                        }
+                    })
+                });
+                let _ = blocking.await;
+                return;
+            }
+            let found = tokio::fs::read(&path)
+                .await
+                .map(|content| search::search_read(&*matcher, &path, &content, &search));
+            if let Some(result) = producer.result_async(path, found).await {
+                let _ = res_tx.send(result).await;
+            }
+        });
+
+        handles.push(handle);
+    }
+
+    drop(res_tx);
+    let _ = walk_handle.await;
+    for handle in handles {
+        let _ = handle.await;
+    }
+    let _ = printer_handle.await;
+}
@@ -0,0 +1,23 @@
+use clap::ValueEnum;
+
+/// How many bytes at the start of a file are sniffed for NUL bytes.
+const SNIFF_LEN: usize = 8 * 1024;
+
+/// What to do with files that look like binary data.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BinaryMode {
+    /// Don't search binary files at all
+    #[default]
+    Skip,
+    /// Search binary files as if they were text
+    Text,
+    /// Search binary files, but only report that they match
+    Report,
+}
+
+/// Returns true when the first block of `content` contains a NUL byte,
+/// which is the same heuristic grep uses to tell binary files from text.
+pub fn is_binary(content: &[u8]) -> bool {
+    let head = &content[..content.len().min(SNIFF_LEN)];
+    head.contains(&0)
+}
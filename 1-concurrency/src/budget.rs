@@ -0,0 +1,73 @@
+use std::sync::{Condvar, Mutex};
+use tokio::sync::Notify;
+
+/// Caps how many bytes of results may be in flight between the workers and the printer.
+/// Workers reserve the size of a result before they hand it over and the printer releases
+/// it once the result is printed, so a slow file that holds up the reorder buffer makes the
+/// workers wait instead of letting memory grow without limit.
+pub struct Budget {
+    limit: usize,
+    used: Mutex<usize>,
+    /// Wakes up blocked threads when bytes are released.
+    released: Condvar,
+    /// Wakes up waiting tasks when bytes are released.
+    released_async: Notify,
+}
+
+impl Budget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+            released_async: Notify::new(),
+        }
+    }
+
+    /// Reserve `bytes`, blocking the current thread until they fit in the budget.
+    /// A single result larger than the whole budget is let through once nothing else is in flight.
+    pub fn acquire(&self, bytes: usize) {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        while !self.fits(*used, bytes) {
+            used = self.released.wait(used).unwrap_or_else(|e| e.into_inner());
+        }
+        *used += bytes;
+    }
+
+    /// Reserve `bytes`, waiting asynchronously until they fit in the budget.
+    pub async fn acquire_async(&self, bytes: usize) {
+        loop {
+            let notified = self.released_async.notified();
+            tokio::pin!(notified);
+            // Register before checking, so a release in between isn't missed.
+            notified.as_mut().enable();
+            if self.try_acquire(bytes) {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Give back `bytes` that were reserved earlier.
+    pub fn release(&self, bytes: usize) {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        *used -= bytes;
+        drop(used);
+        self.released.notify_all();
+        self.released_async.notify_waiters();
+    }
+
+    fn try_acquire(&self, bytes: usize) -> bool {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        if self.fits(*used, bytes) {
+            *used += bytes;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn fits(&self, used: usize, bytes: usize) -> bool {
+        used == 0 || used + bytes <= self.limit
+    }
+}
@@ -0,0 +1,96 @@
+use clap::ValueEnum;
+use std::fmt::{Display, Formatter};
+use std::io::IsTerminal;
+
+/// The environment variable that overrides the default colors, e.g. `path=35:match=1;31`.
+pub const COLORS_ENV: &str = "MYGREP_COLORS";
+
+/// When to color the output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when stdout is a terminal and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether this choice means coloring the output of the current process.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none()
+                    && std::env::var("TERM").map_or(true, |t| t != "dumb")
+            }
+        }
+    }
+}
+
+/// The ANSI SGR parameters (like `1;31` for bold red) used for every part of the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colors {
+    /// File paths in headers and in `-l`/`-c` output
+    pub path: String,
+
+    /// Line numbers
+    pub line: String,
+
+    /// Columns
+    pub column: String,
+
+    /// The matched bytes, or their replacement
+    pub matched: String,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            path: "35".to_string(),
+            line: "32".to_string(),
+            column: "33".to_string(),
+            matched: "1;31".to_string(),
+        }
+    }
+}
+
+impl Colors {
+    /// The default colors, with the overrides from `MYGREP_COLORS` applied.
+    pub fn from_env() -> Self {
+        let mut colors = Self::default();
+        if let Ok(spec) = std::env::var(COLORS_ENV) {
+            colors.apply(&spec);
+        }
+        colors
+    }
+
+    /// Apply a spec of `name=sgr` pairs separated by `:`. Unknown names and
+    /// values that aren't SGR parameters are ignored, like `GREP_COLORS` does.
+    pub fn apply(&mut self, spec: &str) {
+        for (name, value) in spec.split(':').filter_map(|pair| pair.split_once('=')) {
+            if !value.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                continue;
+            }
+            let slot = match name {
+                "path" => &mut self.path,
+                "line" => &mut self.line,
+                "column" => &mut self.column,
+                "match" => &mut self.matched,
+                _ => continue,
+            };
+            *slot = value.to_string();
+        }
+    }
+}
+
+/// Something displayed in the given SGR style.
+pub struct Styled<'a, T>(pub &'a str, pub T);
+
+impl<T: Display> Display for Styled<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\x1b[{}m{}\x1b[0m", self.0, self.1)
+    }
+}
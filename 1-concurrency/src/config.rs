@@ -0,0 +1,107 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The environment variable that names the config file to use instead of `.mygreprc`.
+/// Set to an empty string, no config file is read.
+pub const CONFIG_ENV: &str = "MYGREP_CONFIG";
+
+/// The name of the config file, looked for in the working directory and its ancestors,
+/// then in the home directory.
+pub const CONFIG_FILE: &str = ".mygreprc";
+
+/// The config file to read: the one `MYGREP_CONFIG` names, or else the closest `.mygreprc`.
+pub fn find() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_ENV) {
+        return (!path.is_empty()).then(|| PathBuf::from(path));
+    }
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(Path::to_path_buf)
+        .chain(std::env::var_os("HOME").map(PathBuf::from))
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|file| file.is_file())
+}
+
+/// The arguments in the config file at `path`, to go before the ones on the command line,
+/// so that flags on the command line win. Every line holds one flag, with its value after
+/// `=` or whitespace, such as `--kind=rayon`, `-j 4` or `--exclude *.min.js`. Blank lines
+/// and lines starting with `#` are skipped. Patterns and paths can't be set.
+pub fn load(path: &Path) -> io::Result<Vec<OsString>> {
+    let content = fs::read_to_string(path)?;
+    let mut args = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !line.starts_with('-') || line == "-" || line == "--" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: expected a flag, found {:?}", i + 1, line),
+            ));
+        }
+        // Each line becomes a single argument, so a value starting with `-` stays a value.
+        let arg = match line.split_once(char::is_whitespace) {
+            Some((flag, value)) if flag.starts_with("--") && !flag.contains('=') => {
+                format!("{}={}", flag, value.trim_start())
+            }
+            Some((flag, value)) if !flag.starts_with("--") => {
+                format!("{}{}", flag, value.trim_start())
+            }
+            _ => line.to_string(),
+        };
+        args.push(OsString::from(arg));
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_str(content: &str) -> io::Result<Vec<String>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(&path, content)?;
+        let args = load(&path)?;
+        Ok(args.into_iter().map(|a| a.into_string().unwrap()).collect())
+    }
+
+    #[test]
+    fn every_line_is_one_argument() {
+        let args =
+            load_str("# defaults\n\n-j 4\n--kind rayon\n  --glob=a b  \n--exclude\t*.min.js\n-n\n")
+                .unwrap();
+        assert_eq!(
+            args,
+            [
+                "-j4",
+                "--kind=rayon",
+                "--glob=a b",
+                "--exclude=*.min.js",
+                "-n"
+            ]
+        );
+    }
+
+    #[test]
+    fn values_that_look_like_flags_stay_values() {
+        let args = load_str("--exclude -n\n-g -x\n").unwrap();
+        assert_eq!(args, ["--exclude=-n", "-g-x"]);
+    }
+
+    #[test]
+    fn lines_that_are_not_flags_are_rejected() {
+        for content in ["foo\n", "-n\nsrc/\n", "-\n", "--\n"] {
+            let err = load_str(content).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", content);
+        }
+        let err = load_str("-n\n\nfoo bar\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3: expected a flag, found \"foo bar\""
+        );
+    }
+}
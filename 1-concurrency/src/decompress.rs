@@ -0,0 +1,36 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// The compression formats `-z` knows how to look inside, recognized by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Format {
+    /// The format of `path`, or `None` if it doesn't look compressed.
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "tgz" => Some(Self::Gzip),
+            "bz2" | "tbz2" => Some(Self::Bzip2),
+            "xz" | "txz" => Some(Self::Xz),
+            "zst" | "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Wrap `file` in a reader that yields the decompressed bytes.
+    pub fn decoder(self, file: File) -> std::io::Result<Box<dyn Read + Send>> {
+        let file = BufReader::new(file);
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(file)),
+            Self::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(file)),
+            Self::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(file)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(file)?),
+        })
+    }
+}
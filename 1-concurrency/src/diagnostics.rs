@@ -0,0 +1,45 @@
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::search;
+
+/// Collects the errors met during a search, such as unreadable files or directories.
+/// Each one is printed on stderr unless messages are suppressed, and all of them are
+/// counted, so the caller can tell "no match" apart from "something went wrong".
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    no_messages: bool,
+    errors: AtomicUsize,
+}
+
+impl Diagnostics {
+    pub fn new(no_messages: bool) -> Self {
+        Self {
+            no_messages,
+            errors: AtomicUsize::new(0),
+        }
+    }
+
+    /// Record an error that already says what it is about.
+    pub fn error(&self, err: impl Display) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if !self.no_messages {
+            eprintln!("mygrep: {}", err);
+        }
+    }
+
+    /// Record an error that happened while searching `path`.
+    pub fn file_error(&self, path: &Path, err: impl Display) {
+        if search::is_stdin(path) {
+            self.error(format_args!("<stdin>: {}", err));
+        } else {
+            self.error(format_args!("{}: {}", path.display(), err));
+        }
+    }
+
+    /// How many errors were recorded so far.
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+}
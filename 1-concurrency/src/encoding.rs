@@ -0,0 +1,173 @@
+use clap::ValueEnum;
+use std::io;
+
+/// How many bytes at the start of a file are enough to recognize a byte order mark.
+pub const SNIFF_LEN: usize = 3;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// The text encoding files are read in. Everything that isn't UTF-8 is transcoded to UTF-8
+/// before it is searched, so offsets in the results are offsets into the transcoded text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// Transcode files that start with a UTF-16 byte order mark, strip a UTF-8 one and
+    /// search the rest as is
+    #[default]
+    Auto,
+    /// Search every file as is
+    #[value(name = "utf-8")]
+    Utf8,
+    /// UTF-16, little endian
+    #[value(name = "utf-16le")]
+    Utf16Le,
+    /// UTF-16, big endian
+    #[value(name = "utf-16be")]
+    Utf16Be,
+    /// ISO 8859-1, every byte is the code point of the same value
+    #[value(name = "latin-1")]
+    Latin1,
+}
+
+impl Encoding {
+    /// The encoding a file that starts with `head` has to be transcoded from, or `None`
+    /// when it can be searched as is. `head` needs at most `SNIFF_LEN` bytes.
+    pub fn of(self, head: &[u8]) -> Option<Self> {
+        match self {
+            Self::Auto if head.starts_with(UTF16LE_BOM) => Some(Self::Utf16Le),
+            Self::Auto if head.starts_with(UTF16BE_BOM) => Some(Self::Utf16Be),
+            Self::Auto if head.starts_with(UTF8_BOM) => Some(Self::Utf8),
+            Self::Auto | Self::Utf8 => None,
+            _ => Some(self),
+        }
+    }
+
+    /// The name `--encoding` knows this encoding by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Latin1 => "latin-1",
+        }
+    }
+
+    /// The byte order mark of this encoding.
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf16Le => UTF16LE_BOM,
+            Self::Utf16Be => UTF16BE_BOM,
+            Self::Auto | Self::Utf8 => UTF8_BOM,
+            Self::Latin1 => b"",
+        }
+    }
+
+    /// Decode `content` to UTF-8, without its byte order mark. Anything that can't be
+    /// decoded, like a lone surrogate, becomes U+FFFD.
+    pub fn decode(self, content: &[u8]) -> Vec<u8> {
+        let content = content.strip_prefix(self.bom()).unwrap_or(content);
+        let text: String = match self {
+            Self::Utf16Le | Self::Utf16Be => {
+                let units = content.chunks(2).map(|pair| match (self, pair) {
+                    (Self::Utf16Le, &[lo, hi]) => u16::from_le_bytes([lo, hi]),
+                    (_, &[hi, lo]) => u16::from_be_bytes([hi, lo]),
+                    // A trailing odd byte is no code unit.
+                    _ => 0xFFFD,
+                });
+                char::decode_utf16(units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            Self::Latin1 => content.iter().map(|&b| char::from(b)).collect(),
+            Self::Auto | Self::Utf8 => return content.to_vec(),
+        };
+        text.into_bytes()
+    }
+
+    /// Encode UTF-8 `text` back to this encoding, after `bom` if the original had one.
+    /// Fails on the first character Latin-1 can't represent.
+    pub fn encode(self, text: &[u8], bom: bool) -> io::Result<Vec<u8>> {
+        let text = String::from_utf8_lossy(text);
+        let mut out = if bom { self.bom().to_vec() } else { Vec::new() };
+        match self {
+            Self::Utf16Le => out.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Self::Utf16Be => out.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            Self::Latin1 => {
+                for c in text.chars() {
+                    let byte = u8::try_from(c).map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{:?} can't be written in {}", c, self.name()),
+                        )
+                    })?;
+                    out.push(byte);
+                }
+            }
+            Self::Auto | Self::Utf8 => out.extend_from_slice(text.as_bytes()),
+        }
+        Ok(out)
+    }
+
+    /// Whether `content` starts with the byte order mark of this encoding.
+    pub fn has_bom(self, content: &[u8]) -> bool {
+        !self.bom().is_empty() && content.starts_with(self.bom())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_sniffs_byte_order_marks() {
+        assert_eq!(Encoding::Auto.of(b"\xFF\xFEh\0"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::Auto.of(b"\xFE\xFF\0h"), Some(Encoding::Utf16Be));
+        assert_eq!(Encoding::Auto.of(b"\xEF\xBB\xBFh"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::Auto.of(b"hel"), None);
+        assert_eq!(Encoding::Utf8.of(b"\xFF\xFEh"), None);
+        assert_eq!(Encoding::Latin1.of(b"hel"), Some(Encoding::Latin1));
+    }
+
+    #[test]
+    fn utf8_byte_order_mark_is_stripped_and_restored() {
+        let content = b"\xEF\xBB\xBFhello\n";
+        let encoding = Encoding::Auto.of(content).unwrap();
+        assert_eq!(encoding.decode(content), b"hello\n");
+        let encoded = encoding.encode(b"hello\n", encoding.has_bom(content));
+        assert_eq!(encoded.unwrap(), content);
+    }
+
+    #[test]
+    fn decode_and_encode_round_trip() {
+        let text = "caf\u{e9} na\u{ef}ve\r\n";
+        for (encoding, content) in [
+            (
+                Encoding::Utf16Le,
+                b"\xFF\xFEc\0a\0f\0\xE9\0 \0n\0a\0\xEF\0v\0e\0\r\0\n\0".to_vec(),
+            ),
+            (
+                Encoding::Utf16Be,
+                b"\xFE\xFF\0c\0a\0f\0\xE9\0 \0n\0a\0\xEF\0v\0e\0\r\0\n".to_vec(),
+            ),
+            (Encoding::Latin1, b"caf\xE9 na\xEFve\r\n".to_vec()),
+        ] {
+            assert_eq!(encoding.decode(&content), text.as_bytes(), "{:?}", encoding);
+            let encoded = encoding.encode(text.as_bytes(), encoding.has_bom(&content));
+            assert_eq!(encoded.unwrap(), content, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn latin1_fails_on_characters_it_cannot_represent() {
+        let err = Encoding::Latin1.encode("caf\u{e9} \u{65e5}".as_bytes(), false);
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            Encoding::Utf16Le
+                .encode("\u{65e5}".as_bytes(), false)
+                .unwrap(),
+            b"\xE5\x65"
+        );
+    }
+}
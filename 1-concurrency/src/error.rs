@@ -0,0 +1,24 @@
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid pattern\n{0}")]
+    Regex(#[from] regex::Error),
+
+    #[cfg(feature = "pcre2")]
+    #[error("invalid pattern\n{0}")]
+    Pcre2(#[from] pcre2::Error),
+
+    #[error("{0}")]
+    Unsupported(&'static str),
+
+    #[error(transparent)]
+    Walk(#[from] ignore::Error),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Watch(#[from] notify::Error),
+}
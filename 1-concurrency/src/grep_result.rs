@@ -1,23 +1,169 @@
 use std::{path::PathBuf, ops::Range, fmt::{Display, Formatter}};
 
+use crate::encoding::Encoding;
+
 /// This structure represents the matches that the tool found in **a single file**.
 /// It implements `Display`, so it can be pretty-printed.
-/// This struct and the `Display` trait implementation do NOT need to be edited.
 pub struct GrepResult {
     /// the path of the search result
     pub path: PathBuf,
 
-    /// the contents of that file
-    pub content: Vec<u8>,
+    /// The lines of the file that are needed to print the matches: every line that
+    /// contains part of a match, plus the requested context lines, in file order.
+    /// The rest of the file is not kept around.
+    pub lines: Vec<Line>,
 
     /// which ranges in the file match the filter.
     /// A file may contain more than one match. Each match is a Range,
-    /// which is a start and end byte offset in the content field.
+    /// which is a start and end byte offset in the original file
+    /// (in its UTF-8 transcoding, see `encoding`).
     pub ranges: Vec<Range<usize>>,
 
+    /// When replacing, the replacement text for every range in `ranges`. Empty otherwise.
+    pub replacements: Vec<Vec<u8>>,
+
     /// The index of this search result (ie. a counter of how many files have had a match before this
     /// one). Note that this count must always increase as the results are printed.
     pub search_ctr: usize,
+
+    /// Whether the file was detected as binary. Binary results are printed as a single
+    /// "binary file X matches" line instead of a context dump.
+    pub binary: bool,
+
+    /// The encoding the file was transcoded from before searching it, if it wasn't UTF-8
+    /// without a byte order mark.
+    /// Offsets in `ranges` and `lines` are then offsets into the transcoded UTF-8 text,
+    /// not into the file on disk.
+    pub encoding: Option<Encoding>,
+}
+
+/// A single line of a searched file.
+pub struct Line {
+    /// 0-based line number in the file
+    pub number: usize,
+
+    /// byte offset of the start of the line in the file
+    pub offset: usize,
+
+    /// the contents of the line, without its line terminator
+    pub text: Vec<u8>,
+}
+
+impl Line {
+    /// Byte offset just past the last byte of the line's text.
+    pub fn end(&self) -> usize {
+        self.offset + self.text.len()
+    }
+
+    /// The terminator between this line and `next`, the line right after it in the file.
+    pub(crate) fn terminator(&self, next: &Line) -> &'static [u8] {
+        if next.offset - self.end() == 2 {
+            b"\r\n"
+        } else {
+            b"\n"
+        }
+    }
+}
+
+/// The text of `lines`, which follow each other in the file, joined by the terminators
+/// between them there. Offsets into it are file offsets, less the first line's offset.
+pub(crate) fn join<'a>(lines: impl IntoIterator<Item = &'a Line>) -> Vec<u8> {
+    let mut text = Vec::new();
+    let mut previous: Option<&Line> = None;
+    for line in lines {
+        if let Some(previous) = previous {
+            text.extend_from_slice(previous.terminator(line));
+        }
+        text.extend_from_slice(&line.text);
+        previous = Some(line);
+    }
+    text
+}
+
+impl GrepResult {
+    /// Roughly how many bytes of memory this result holds on to.
+    pub fn memory_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.path.as_os_str().len()
+            + self
+                .lines
+                .iter()
+                .map(|l| std::mem::size_of::<Line>() + l.text.len())
+                .sum::<usize>()
+            + self.ranges.len() * std::mem::size_of::<Range<usize>>()
+            + self.replacements.iter().map(Vec::len).sum::<usize>()
+    }
+
+    /// `text`, which starts at file offset `offset`, with the replacements applied to
+    /// every match that overlaps it. Without replacements, `text` itself.
+    pub fn replaced(&self, offset: usize, text: &[u8]) -> Vec<u8> {
+        if self.replacements.is_empty() {
+            return text.to_vec();
+        }
+        crate::replace::apply(text, offset, &self.ranges, &self.replacements)
+    }
+
+    /// The number of lines that contain a match. Binary results don't keep their lines,
+    /// so for those this is the number of matches.
+    pub fn matched_lines(&self) -> usize {
+        if self.binary {
+            return self.ranges.len();
+        }
+        self.lines
+            .iter()
+            .filter(|l| self.match_column(l).is_some())
+            .count()
+    }
+
+    /// Keep only the matches on the first `max` matching lines, and no lines after them.
+    pub fn truncate_matches(&mut self, max: usize) {
+        if self.matched_lines() <= max {
+            return;
+        }
+        if self.binary {
+            self.ranges.truncate(max);
+            return;
+        }
+        let last = self
+            .lines
+            .iter()
+            .filter(|l| self.match_column(l).is_some())
+            .nth(max.saturating_sub(1))
+            .filter(|_| max > 0)
+            .map_or(0, Line::end);
+        let keep = self.ranges.partition_point(|r| max > 0 && r.start <= last);
+        self.ranges.truncate(keep);
+        self.replacements.truncate(keep);
+        self.lines.retain(|l| max > 0 && l.offset <= last);
+    }
+
+    /// Index into `lines` of the line containing byte `offset`, if that line was kept.
+    pub fn line_index(&self, offset: usize) -> Option<usize> {
+        let i = self.lines.partition_point(|l| l.offset <= offset).checked_sub(1)?;
+        (offset <= self.lines[i].end()).then_some(i)
+    }
+
+    /// The 1-based column of the first match that starts on `line`. Lines that are
+    /// only covered by the tail of a match that started earlier report column 1.
+    pub fn match_column(&self, line: &Line) -> Option<usize> {
+        let i = self.ranges.partition_point(|r| r.start < line.offset);
+        if let Some(r) = self.ranges.get(i).filter(|r| r.start <= line.end()) {
+            return Some(r.start - line.offset + 1);
+        }
+        let prev = self.ranges[..i].last()?;
+        (prev.end > line.offset).then_some(1)
+    }
+
+    /// The bytes of every kept line that `range` touches, with the terminators between
+    /// them, together with the file offset at which they start.
+    fn span(&self, range: &Range<usize>) -> Option<(usize, Vec<u8>)> {
+        let first = self.line_index(range.start)?;
+        let last = self
+            .line_index(range.end.max(range.start + 1) - 1)
+            .unwrap_or(first)
+            .max(first);
+        Some((self.lines[first].offset, join(&self.lines[first..=last])))
+    }
 }
 
 impl Display for GrepResult {
@@ -28,41 +174,76 @@ impl Display for GrepResult {
             return Ok(());
         }
 
+        if self.binary {
+            return writeln!(f, "binary file {:?} matches", self.path);
+        }
+
         writeln!(f, ">>> (#{}) {:?}", self.search_ctr, self.path)?;
-        for range in &self.ranges {
-            // Find the index of the first byte before the range that is a newline character, plus one.
-            let mut ctx_start = (0..range.start)
-                .rev()
-                .find(|p| self.content[*p] == b'\n' || self.content[*p] == b'\r')
-                .map_or(0, |p| p + 1);
-
-            // Find the index of the first byte after the range that is a newline character (not minus one because it is exclusive)
-            let mut ctx_end = (range.end..self.content.len())
-                .find(|p| self.content[*p] == b'\n' || self.content[*p] == b'\r')
-                .unwrap_or(self.content.len());
-
-            // if the context is too large, reduce its size
-            if ctx_start + MAX_CONTEXT < range.start {
-                ctx_start = range.start - MAX_CONTEXT;
-            }
-            if ctx_end > range.end + MAX_CONTEXT {
-                ctx_end = range.end + MAX_CONTEXT;
+        for (i, range) in self.ranges.iter().enumerate() {
+            // The lines around the match, from the start of its first line to the end of its last line.
+            let Some((base, mut content)) = self.span(range) else {
+                continue;
+            };
+            // The match, relative to the start of `content`.
+            let start = range.start - base;
+            let mut end = range.end - base;
+
+            // When replacing, show the replacement where the match was.
+            if let Some(replacement) = self.replacements.get(i) {
+                let tail = content.split_off(end.min(content.len()));
+                content.truncate(start);
+                content.extend_from_slice(replacement);
+                content.extend_from_slice(&tail);
+                end = start + replacement.len();
             }
 
-            // Finally, print the result
-            writeln!(
-                f,
-                "{}",
-                String::from_utf8_lossy(&self.content[ctx_start..ctx_end])
-            )?;
-            // Print ^^^^ underneath matched part
-            writeln!(
-                f,
-                "{}{}{}",
-                " ".repeat(range.start - ctx_start),
-                "^".repeat(range.end - range.start),
-                " ".repeat(ctx_end - range.end)
-            )?;
+            // A match that spans lines is printed line by line, each one underlined where the match covers it.
+            let mut line_start = 0;
+            let mut pieces = content.split(|&b| b == b'\n').peekable();
+            while let Some(piece) = pieces.next() {
+                // Every line but the last still ends in the `\r` of a `\r\n` terminator.
+                let text = match pieces.peek() {
+                    Some(_) => piece.strip_suffix(b"\r").unwrap_or(piece),
+                    None => piece,
+                };
+                let line_end = line_start + text.len();
+                let next_start = line_start + piece.len() + 1;
+                if line_start > end || start > line_end {
+                    line_start = next_start;
+                    continue;
+                }
+                let mut start = start.clamp(line_start, line_end) - line_start;
+                let mut end = end.clamp(line_start, line_end) - line_start;
+                line_start = next_start;
+
+                let mut ctx_start = 0;
+                let mut ctx_end = text.len();
+
+                // if the context is too large, reduce its size
+                if ctx_start + MAX_CONTEXT < start {
+                    ctx_start = start - MAX_CONTEXT;
+                }
+                if ctx_end > end + MAX_CONTEXT {
+                    ctx_end = end + MAX_CONTEXT;
+                }
+                start -= ctx_start;
+                end -= ctx_start;
+
+                // Finally, print the result
+                writeln!(
+                    f,
+                    "{}",
+                    String::from_utf8_lossy(&text[ctx_start..ctx_end])
+                )?;
+                // Print ^^^^ underneath matched part
+                writeln!(
+                    f,
+                    "{}{}{}",
+                    " ".repeat(start),
+                    "^".repeat(end - start),
+                    " ".repeat((ctx_end - ctx_start).saturating_sub(end))
+                )?;
+            }
         }
 
         Ok(())
@@ -0,0 +1,492 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::archive;
+use crate::decompress;
+use crate::diagnostics::Diagnostics;
+use crate::encoding::{self, Encoding};
+use crate::matcher::{self, Engine, MatchOptions};
+use crate::prefilter;
+use crate::search::SearchOptions;
+use crate::walker::{WalkOptions, Walker};
+use crate::Error;
+
+/// The name of the index file, in the directory the index was built for.
+pub const INDEX_FILE: &str = ".mygrep-index";
+
+const MAGIC: &[u8; 8] = b"MYGREPIX";
+const VERSION: u32 = 1;
+
+/// Files with more distinct trigrams than this contain nearly every literal, so they are
+/// left out of the index and always searched.
+const MAX_TRIGRAMS: usize = 1 << 18;
+
+/// The trigrams of every file below a directory, for `--indexed`. A file that isn't in the
+/// index, or that changed since it was indexed, is always searched, so a stale index only
+/// costs speed, never matches.
+pub struct Index {
+    /// The canonical path of the indexed directory
+    root: PathBuf,
+    /// Keyed by path relative to `root`
+    files: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct Entry {
+    stamp: Stamp,
+    /// Sorted ASCII-lowercased trigrams, or `None` if the file can't be narrowed down by them
+    trigrams: Option<Vec<u32>>,
+}
+
+/// Modification time and size, to tell whether a file changed since it was indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    secs: u64,
+    nanos: u32,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(metadata: &Metadata) -> Option<Self> {
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            secs: modified.as_secs(),
+            nanos: modified.subsec_nanos(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// What `Index::build` did.
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+    /// Files in the index
+    pub files: usize,
+    /// Files that were new or had changed, and were read again
+    pub updated: usize,
+    /// Files that were in the old index but are gone
+    pub removed: usize,
+}
+
+impl Index {
+    /// Build the index of `root` and store it in `root/.mygrep-index`. Files whose
+    /// modification time and size are unchanged since the last build are not read again.
+    pub fn build(root: &Path, walk: &WalkOptions) -> Result<Summary, Error> {
+        let root = root.canonicalize()?;
+        let file = root.join(INDEX_FILE);
+        let previous = Index::load(&file).ok().filter(|index| index.root == root);
+        let old = previous.map(|index| index.files).unwrap_or_default();
+
+        let diagnostics = Arc::new(Diagnostics::new(false));
+        let paths = Walker::new(std::slice::from_ref(&root), walk, diagnostics)?.collect();
+        let entries: Vec<(PathBuf, Entry, bool)> = paths
+            .into_par_iter()
+            .filter_map(|path| {
+                let stamp = Stamp::of(&fs::metadata(&path).ok()?)?;
+                let relative = path.strip_prefix(&root).ok()?.to_path_buf();
+                if relative == Path::new(INDEX_FILE) {
+                    return None;
+                }
+                if let Some(entry) = old.get(&relative).filter(|e| e.stamp == stamp) {
+                    return Some((relative, entry.clone(), false));
+                }
+                let trigrams = file_trigrams(&path).ok()?;
+                Some((relative, Entry { stamp, trigrams }, true))
+            })
+            .collect();
+
+        let updated = entries.iter().filter(|(_, _, updated)| *updated).count();
+        let files: HashMap<PathBuf, Entry> = entries
+            .into_iter()
+            .map(|(path, entry, _)| (path, entry))
+            .collect();
+        let removed = old.keys().filter(|path| !files.contains_key(*path)).count();
+        let index = Index { root, files };
+        index.store(&file)?;
+        Ok(Summary {
+            files: index.files.len(),
+            updated,
+            removed,
+        })
+    }
+
+    /// The index of the closest directory at or above `dir` that has one.
+    pub fn find(dir: &Path) -> io::Result<Self> {
+        let dir = dir.canonicalize()?;
+        let file = dir
+            .ancestors()
+            .map(|d| d.join(INDEX_FILE))
+            .find(|f| f.is_file())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no index found, build one with `mygrep index build`",
+                )
+            })?;
+        Index::load(&file)
+    }
+
+    fn load(file: &Path) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(file)?);
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(&mut r)? != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a mygrep index", file.display()),
+            ));
+        }
+        let root = PathBuf::from(read_string(&mut r)?);
+        let count = read_u32(&mut r)?;
+        let mut files = HashMap::with_capacity(count as usize);
+        for _ in 0..count {
+            let path = PathBuf::from(read_string(&mut r)?);
+            let stamp = Stamp {
+                secs: read_u64(&mut r)?,
+                nanos: read_u32(&mut r)?,
+                len: read_u64(&mut r)?,
+            };
+            let trigrams = match read_u32(&mut r)? {
+                u32::MAX => None,
+                n => Some(
+                    (0..n)
+                        .map(|_| read_u32(&mut r))
+                        .collect::<io::Result<_>>()?,
+                ),
+            };
+            files.insert(path, Entry { stamp, trigrams });
+        }
+        Ok(Self { root, files })
+    }
+
+    /// Write the index next to `file` first and rename it over `file`, so a search never
+    /// reads a half-written index.
+    fn store(&self, file: &Path) -> io::Result<()> {
+        let tmp = file.with_extension(format!("{}.tmp", std::process::id()));
+        let result = (|| {
+            let mut w = BufWriter::new(OpenOptions::new().write(true).create_new(true).open(&tmp)?);
+            w.write_all(MAGIC)?;
+            w.write_all(&VERSION.to_le_bytes())?;
+            write_string(&mut w, &self.root)?;
+            // Paths that aren't valid UTF-8 are left out, so those files are always searched.
+            let files: Vec<_> = self
+                .files
+                .iter()
+                .filter(|(p, _)| p.to_str().is_some())
+                .collect();
+            w.write_all(&(files.len() as u32).to_le_bytes())?;
+            for (path, entry) in files {
+                write_string(&mut w, path)?;
+                w.write_all(&entry.stamp.secs.to_le_bytes())?;
+                w.write_all(&entry.stamp.nanos.to_le_bytes())?;
+                w.write_all(&entry.stamp.len.to_le_bytes())?;
+                match &entry.trigrams {
+                    Some(trigrams) => {
+                        w.write_all(&(trigrams.len() as u32).to_le_bytes())?;
+                        for t in trigrams {
+                            w.write_all(&t.to_le_bytes())?;
+                        }
+                    }
+                    None => w.write_all(&u32::MAX.to_le_bytes())?,
+                }
+            }
+            w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            fs::rename(&tmp, file)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    /// The entry of the file at `path`, which is absolute or relative to `cwd`.
+    fn entry(&self, cwd: &Path, path: &Path) -> Option<&Entry> {
+        let mut absolute = PathBuf::new();
+        for component in cwd.join(path).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    absolute.pop();
+                }
+                c => absolute.push(c),
+            }
+        }
+        self.files.get(absolute.strip_prefix(&self.root).ok()?)
+    }
+}
+
+/// Decides from an index which files can't contain a match, so the walker can skip them.
+pub struct Candidates {
+    index: Index,
+    cwd: PathBuf,
+    /// For every literal a match may contain, the trigrams a file needs to contain it.
+    /// `None` when the patterns don't narrow anything down.
+    literals: Option<Vec<Vec<u32>>>,
+}
+
+impl Candidates {
+    /// Candidates for `patterns`, compiled with `options`, from `index`. Nothing is ruled
+    /// out when files without a match are reported too, as with `invert` or
+    /// `files_without_match`, when files are transcoded from a single byte encoding, or
+    /// for PCRE2 patterns, whose syntax the literals can't be taken from.
+    pub fn new(
+        index: Index,
+        patterns: &[String],
+        options: &MatchOptions,
+        search: &SearchOptions,
+    ) -> Self {
+        let narrows = !search.invert
+            && !search.files_without_match
+            && matches!(search.encoding, Encoding::Auto | Encoding::Utf8)
+            && options.engine == Engine::Default;
+        let (pattern, ignore_case) = matcher::combine(patterns, options);
+        let literals = narrows
+            .then(|| prefilter::required_literals(&pattern))
+            .flatten()
+            .map(|literals| {
+                literals
+                    .iter()
+                    .map(|literal| literal_trigrams(literal, ignore_case))
+                    .collect()
+            });
+        let cwd = std::env::current_dir().unwrap_or_default();
+        Self {
+            index,
+            cwd: cwd.canonicalize().unwrap_or(cwd),
+            literals,
+        }
+    }
+
+    /// Whether the file at `path` may contain a match. False only if it is indexed,
+    /// unchanged since, and lacks some trigram of every literal a match may contain.
+    pub fn may_match(&self, path: &Path, metadata: &Metadata) -> bool {
+        let Some(literals) = &self.literals else {
+            return true;
+        };
+        let Some(entry) = self.index.entry(&self.cwd, path) else {
+            return true;
+        };
+        let (Some(trigrams), Some(stamp)) = (&entry.trigrams, Stamp::of(metadata)) else {
+            return true;
+        };
+        stamp != entry.stamp
+            || literals
+                .iter()
+                .any(|needed| needed.iter().all(|t| trigrams.binary_search(t).is_ok()))
+    }
+}
+
+/// The trigrams of the file at `path`, or `None` for files whose searched text isn't
+/// their raw bytes (compressed files, archives, UTF-16) or that have too many of them.
+fn file_trigrams(path: &Path) -> io::Result<Option<Vec<u32>>> {
+    if decompress::Format::of(path).is_some() || archive::Format::of(path).is_some() {
+        return Ok(None);
+    }
+    let content = fs::read(path)?;
+    let head = &content[..content.len().min(encoding::SNIFF_LEN)];
+    if Encoding::Auto.of(head).is_some() {
+        return Ok(None);
+    }
+    let mut trigrams: Vec<u32> = content
+        .windows(3)
+        .map(|w| trigram(w[0], w[1], w[2]))
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    Ok((trigrams.len() <= MAX_TRIGRAMS).then_some(trigrams))
+}
+
+/// The trigrams a file needs to contain `literal`. When matching case insensitively, only
+/// trigrams without letters that also match non-ASCII characters are needed: `k` matches
+/// the Kelvin sign and `s` the long s.
+fn literal_trigrams(literal: &[u8], ignore_case: bool) -> Vec<u32> {
+    let plain = |b: &u8| b.is_ascii() && !matches!(b.to_ascii_lowercase(), b'k' | b's');
+    let mut trigrams: Vec<u32> = literal
+        .windows(3)
+        .filter(|w| !ignore_case || w.iter().all(plain))
+        .map(|w| trigram(w[0], w[1], w[2]))
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+/// Trigrams are ASCII case folded, so one index serves case sensitive and insensitive searches.
+fn trigram(a: u8, b: u8, c: u8) -> u32 {
+    u32::from_be_bytes([
+        0,
+        a.to_ascii_lowercase(),
+        b.to_ascii_lowercase(),
+        c.to_ascii_lowercase(),
+    ])
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let mut buf = vec![0; read_u32(r)? as usize];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_string(w: &mut impl Write, path: &Path) -> io::Result<()> {
+    let s = path.to_string_lossy();
+    w.write_all(&(s.len() as u32).to_le_bytes())?;
+    w.write_all(s.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A directory with a file that contains `needle` and one that doesn't.
+    fn tree() -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join("hit.txt"), "a Needle_42 in a haystack\n").unwrap();
+        fs::write(root.join("miss.txt"), "only hay here\n").unwrap();
+        (dir, root)
+    }
+
+    fn candidates(root: &Path, pattern: &str, options: &MatchOptions) -> Candidates {
+        let index = Index::find(root).unwrap();
+        Candidates::new(
+            index,
+            &[pattern.to_string()],
+            options,
+            &SearchOptions::default(),
+        )
+    }
+
+    fn may_match(candidates: &Candidates, path: &Path) -> bool {
+        candidates.may_match(path, &fs::metadata(path).unwrap())
+    }
+
+    #[test]
+    fn build_only_reads_files_that_changed() {
+        let (_dir, root) = tree();
+        let walk = WalkOptions::default();
+        let summary = Index::build(&root, &walk).unwrap();
+        assert_eq!((summary.files, summary.updated, summary.removed), (2, 2, 0));
+
+        let summary = Index::build(&root, &walk).unwrap();
+        assert_eq!((summary.files, summary.updated, summary.removed), (2, 0, 0));
+
+        fs::write(root.join("hit.txt"), "changed, and longer than before\n").unwrap();
+        fs::write(root.join("new.txt"), "new\n").unwrap();
+        fs::remove_file(root.join("miss.txt")).unwrap();
+        let summary = Index::build(&root, &walk).unwrap();
+        assert_eq!((summary.files, summary.updated, summary.removed), (2, 2, 1));
+    }
+
+    #[test]
+    fn index_survives_storing_and_loading() {
+        let (_dir, root) = tree();
+        Index::build(&root, &WalkOptions::default()).unwrap();
+        let index = Index::find(&root.join("..").join(root.file_name().unwrap())).unwrap();
+        assert_eq!(index.root, root);
+        assert_eq!(index.files.len(), 2);
+        let entry = &index.files[Path::new("hit.txt")];
+        let expected = file_trigrams(&root.join("hit.txt")).unwrap();
+        assert!(entry.trigrams.is_some());
+        assert_eq!(entry.trigrams, expected);
+    }
+
+    #[test]
+    fn files_without_the_literals_are_ruled_out() {
+        let (_dir, root) = tree();
+        Index::build(&root, &WalkOptions::default()).unwrap();
+        let (hit, miss) = (root.join("hit.txt"), root.join("miss.txt"));
+
+        let needle = candidates(&root, r"Needle_\d+", &MatchOptions::default());
+        assert!(may_match(&needle, &hit));
+        assert!(!may_match(&needle, &miss));
+
+        // Trigrams are case folded, whether the search is case sensitive or not.
+        let ignore_case = MatchOptions {
+            ignore_case: true,
+            ..MatchOptions::default()
+        };
+        assert!(may_match(&candidates(&root, "NEEDLE", &ignore_case), &hit));
+        assert!(may_match(
+            &candidates(&root, "needle", &MatchOptions::default()),
+            &hit
+        ));
+
+        // Either literal of an alternation is enough.
+        let either = candidates(&root, "needle|hay", &MatchOptions::default());
+        assert!(may_match(&either, &miss));
+
+        // Without a literal, nothing can be ruled out.
+        let anything = candidates(&root, r"\w+", &MatchOptions::default());
+        assert!(may_match(&anything, &miss));
+    }
+
+    #[test]
+    fn files_that_changed_or_are_not_indexed_are_searched() {
+        let (_dir, root) = tree();
+        Index::build(&root, &WalkOptions::default()).unwrap();
+        let needle = candidates(&root, "Needle", &MatchOptions::default());
+
+        let miss = root.join("miss.txt");
+        fs::write(&miss, "now there is a Needle too\n").unwrap();
+        assert!(may_match(&needle, &miss));
+
+        let new = root.join("new.txt");
+        fs::write(&new, "Needle\n").unwrap();
+        assert!(may_match(&needle, &new));
+    }
+
+    #[test]
+    fn inverted_searches_rule_nothing_out() {
+        let (_dir, root) = tree();
+        Index::build(&root, &WalkOptions::default()).unwrap();
+        let search = SearchOptions {
+            invert: true,
+            ..SearchOptions::default()
+        };
+        let index = Index::find(&root).unwrap();
+        let inverted = Candidates::new(
+            index,
+            &["Needle".to_string()],
+            &MatchOptions::default(),
+            &search,
+        );
+        assert!(may_match(&inverted, &root.join("miss.txt")));
+    }
+
+    #[test]
+    fn files_with_a_byte_order_mark_are_not_narrowed_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bom.txt");
+        fs::write(&path, b"\xEF\xBB\xBFhello\n").unwrap();
+        assert_eq!(file_trigrams(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn case_insensitive_literals_skip_letters_with_non_ascii_matches() {
+        let sensitive = literal_trigrams(b"kiosk", false);
+        assert_eq!(sensitive.len(), 3);
+        assert!(literal_trigrams(b"kiosk", true).is_empty());
+        assert_eq!(
+            literal_trigrams(b"Hello", true),
+            literal_trigrams(b"hello", false)
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use serde::Serialize;
+use std::fmt::{Formatter, Write};
+
+use crate::encoding::Encoding;
+use crate::grep_result::GrepResult;
+
+/// One line of `--json` output. Every file produces a `begin` record, one `match`
+/// record per match and an `end` record. A single `summary` record closes the stream.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record<'a> {
+    Begin {
+        path: &'a str,
+        search_ctr: usize,
+        binary: bool,
+        /// Set when the file was transcoded, `start` and `end` are then UTF-8 offsets
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encoding: Option<&'static str>,
+    },
+    Match {
+        path: &'a str,
+        search_ctr: usize,
+        start: usize,
+        end: usize,
+        line_number: usize,
+        line: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        replacement: Option<String>,
+    },
+    End {
+        path: &'a str,
+        search_ctr: usize,
+        matches: usize,
+    },
+    Summary {
+        files: usize,
+        matches: usize,
+    },
+}
+
+/// Write all records for `result` to `f`, one JSON object per line.
+pub fn write_result(result: &GrepResult, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let path = result.path.to_string_lossy();
+    write_record(
+        f,
+        &Record::Begin {
+            path: &path,
+            search_ctr: result.search_ctr,
+            binary: result.binary,
+            encoding: result.encoding.map(Encoding::name),
+        },
+    )?;
+
+    if !result.binary {
+        for (i, range) in result.ranges.iter().enumerate() {
+            let Some(line) = result.line_index(range.start).map(|i| &result.lines[i]) else {
+                continue;
+            };
+            let line_number = line.number;
+            let line = String::from_utf8_lossy(&line.text);
+            write_record(
+                f,
+                &Record::Match {
+                    path: &path,
+                    search_ctr: result.search_ctr,
+                    start: range.start,
+                    end: range.end,
+                    line_number: line_number + 1,
+                    line: &line,
+                    replacement: result
+                        .replacements
+                        .get(i)
+                        .map(|r| String::from_utf8_lossy(r).into_owned()),
+                },
+            )?;
+        }
+    }
+
+    write_record(
+        f,
+        &Record::End {
+            path: &path,
+            search_ctr: result.search_ctr,
+            matches: result.ranges.len(),
+        },
+    )
+}
+
+/// The closing record with totals over every printed file.
+pub fn summary(files: usize, matches: usize) -> String {
+    serde_json::to_string(&Record::Summary { files, matches }).unwrap_or_default()
+}
+
+fn write_record(f: &mut impl Write, record: &Record) -> std::fmt::Result {
+    let line = serde_json::to_string(record).map_err(|_| std::fmt::Error)?;
+    writeln!(f, "{}", line)
+}
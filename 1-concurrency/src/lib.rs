@@ -0,0 +1,52 @@
+//! The search engine behind the `mygrep` binary.
+//!
+//! Build a [`Searcher`] with a regex, walker and search options and a backend [`Kind`],
+//! then either hand its results to a [`Sink`] with [`Searcher::run`] or pull them one
+//! by one with [`Searcher::iter`]. Results always arrive in `search_ctr` order.
+//!
+//! ```no_run
+//! use mygrep::{Kind, Searcher};
+//! use regex::bytes::Regex;
+//! use std::path::PathBuf;
+//!
+//! let searcher = Searcher::builder(Regex::new("TODO").unwrap())
+//!     .kind(Kind::Rayon)
+//!     .build();
+//! for result in searcher.iter(&[PathBuf::from("src")]).unwrap() {
+//!     println!("{}: {} matches", result.path.display(), result.ranges.len());
+//! }
+//! ```
+
+// The the struct you need to use to print your results.
+pub use crate::grep_result::{GrepResult, Line};
+pub use crate::cancel::CancelToken;
+pub use crate::error::Error;
+pub use crate::searcher::{Kind, Results, Searcher, SearcherBuilder, Sink, Stats};
+
+pub mod binary;
+pub mod cancel;
+pub mod color;
+pub mod config;
+pub mod diagnostics;
+pub mod encoding;
+pub mod grep_result;
+pub mod index;
+pub mod matcher;
+pub mod output;
+pub mod search;
+pub mod walker;
+
+mod archive;
+mod backend;
+mod budget;
+mod decompress;
+mod error;
+mod json;
+mod ordered;
+mod reorder;
+#[cfg(feature = "pcre2")]
+mod pcre;
+mod prefilter;
+mod replace;
+mod searcher;
+mod watch;
@@ -1,324 +1,732 @@
-use clap::{Parser, ValueEnum};
-use regex::bytes::Regex;
+use clap::builder::RangedU64ValueParser;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
+use std::ffi::OsString;
 use std::path::PathBuf;
-
-use std::collections::BTreeMap;
-use std::fs;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
-
-// The the struct you need to use to print your results.
-pub use crate::grep_result::GrepResult;
-
-mod grep_result;
-
-/// Kind selector for the bonus assignment
-#[derive(Debug, Default, Clone, Copy, ValueEnum)]
-enum Kind {
-    #[default]
-    SelfMade,
-    Rayon,
-    Tokio,
-}
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use mygrep::binary::BinaryMode;
+use mygrep::color::{ColorChoice, Colors};
+use mygrep::config;
+use mygrep::encoding::Encoding;
+use mygrep::index::{Candidates, Index};
+use mygrep::matcher::{self, Engine, MatchOptions};
+use mygrep::output::{OutputMode, OutputOptions, Printer};
+use mygrep::search::{self, SearchOptions};
+use mygrep::walker::{self, WalkOptions};
+use mygrep::{CancelToken, Kind, Searcher};
 
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(
+    version,
+    about,
+    // Flags from the config file come first, so the ones on the command line replace them.
+    args_override_self = true,
+    after_help = "Defaults are read from .mygreprc in the current directory or one above it, \
+                  or in the home directory, or from the file MYGREP_CONFIG names. It holds one \
+                  flag per line, like `--kind=rayon` or `-j 4`, and `#` starts a comment line. \
+                  Flags set there can be turned off with their `--no-` form, like `--no-hidden`, \
+                  or `--ignore` and `--messages` for `--no-ignore` and `--no-messages`. \
+                  Patterns, --replace, --max-count and --max-total can't be set there.\n\n\
+                  Run `mygrep index build` to build the index for --indexed. \
+                  To search for the word `index` itself, use `mygrep -e index`."
+)]
 struct Args {
-    /// The regex pattern that the user provided
-    regex: String,
+    /// The regex pattern that the user provided. When -e is used, this is the first path instead
+    #[arg(required_unless_present_any = ["patterns", "type_list"])]
+    regex: Option<String>,
+
+    /// A pattern to search for (may be repeated, a line matching any of them matches)
+    #[arg(short = 'e', long = "regexp", value_name = "PATTERN")]
+    patterns: Vec<String>,
 
     /// Which version of the grep to run
     #[arg(default_value = "self-made", short, long)]
     kind: Kind,
 
-    /// The paths in which mygrep should search, if empty, in the current directory
+    /// The paths in which mygrep should search. `-` means standard input. If empty, stdin is
+    /// searched when it is a pipe or a file, and the current directory otherwise
     paths: Vec<String>,
+
+    /// Match case insensitively
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Match case insensitively unless the pattern contains an uppercase letter
+    #[arg(short = 'S', long)]
+    smart_case: bool,
+
+    /// Only match whole words
+    #[arg(short = 'w', long)]
+    word_regexp: bool,
+
+    /// Only match whole lines
+    #[arg(short = 'x', long)]
+    line_regexp: bool,
+
+    /// Treat the patterns as literal strings
+    #[arg(short = 'F', long)]
+    fixed_strings: bool,
+
+    /// Let matches span lines, e.g. `foo\nbar`. Use `(?s)` to also let `.` match newlines
+    #[arg(short = 'U', long)]
+    multiline: bool,
+
+    /// Use PCRE2 for look-around and backreferences (needs the `pcre2` cargo feature)
+    #[arg(short = 'P', long)]
+    pcre2: bool,
+
+    /// Select the lines that don't match
+    #[arg(short = 'v', long)]
+    invert_match: bool,
+
+    /// Only print the paths of files with matches
+    #[arg(short = 'l', long, conflicts_with_all = ["files_without_match", "count", "json"])]
+    files_with_matches: bool,
+
+    /// Only print the paths of files without matches
+    #[arg(long, conflicts_with_all = ["count", "json"])]
+    files_without_match: bool,
+
+    /// Only print the number of matching lines of every file
+    #[arg(short = 'c', long, conflicts_with = "json")]
+    count: bool,
+
+    /// Print nothing and stop at the first match, the exit code tells whether there was one
+    #[arg(short = 'q', long, conflicts_with_all = ["write", "watch"])]
+    quiet: bool,
+
+    /// Stop searching a file after NUM matching lines
+    #[arg(short = 'm', long, value_name = "NUM")]
+    max_count: Option<usize>,
+
+    /// Stop the whole search after NUM matching lines. Can't be combined with --write,
+    /// since files are rewritten before the lines are counted
+    #[arg(
+        long,
+        value_name = "NUM",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = ["watch", "write"]
+    )]
+    max_total: Option<usize>,
+
+    /// Replace every match with TEMPLATE in the output ($1, ${name} refer to capture groups)
+    #[arg(short = 'r', long, value_name = "TEMPLATE", conflicts_with_all = ["invert_match", "files_without_match"])]
+    replace: Option<String>,
+
+    /// Write the replacements back to the files instead of only previewing them.
+    /// Compressed files can't be written back, so this can't be combined with -z
+    #[arg(long, requires = "replace", conflicts_with = "decompress")]
+    write: bool,
+
+    /// Show the replacements as a unified diff
+    #[arg(long, requires = "replace", conflicts_with = "json")]
+    diff: bool,
+
+    /// Only search files matching this glob (may be repeated)
+    #[arg(short, long = "glob", value_name = "GLOB")]
+    globs: Vec<String>,
+
+    /// Skip files matching this glob (may be repeated)
+    #[arg(long = "exclude", value_name = "GLOB")]
+    excludes: Vec<String>,
+
+    /// Only search files of this type, see --type-list (may be repeated)
+    #[arg(short = 't', long = "type", value_name = "TYPE")]
+    types: Vec<String>,
+
+    /// Skip files of this type (may be repeated)
+    #[arg(short = 'T', long = "type-not", value_name = "TYPE")]
+    types_not: Vec<String>,
+
+    /// Define a file type, e.g. `web:*.{html,css}` (may be repeated)
+    #[arg(long = "type-add", value_name = "NAME:GLOB")]
+    type_defs: Vec<String>,
+
+    /// Print every known file type and its globs, then exit
+    #[arg(long)]
+    type_list: bool,
+
+    /// Skip files larger than SIZE, e.g. 100K, 4M or 1G
+    #[arg(long, value_name = "SIZE", value_parser = walker::parse_size)]
+    max_filesize: Option<u64>,
+
+    /// Only search files modified within AGE, e.g. 30m, 2h or 7d
+    #[arg(long, value_name = "AGE", value_parser = walker::parse_age)]
+    newer_than: Option<Duration>,
+
+    /// Descend at most NUM directories below every path
+    #[arg(long, value_name = "NUM")]
+    max_depth: Option<usize>,
+
+    /// Follow symbolic links, reporting link loops instead of descending into them
+    #[arg(short = 'L', long)]
+    follow: bool,
+
+    /// Don't descend into directories on other file systems
+    #[arg(long)]
+    one_file_system: bool,
+
+    /// Search hidden files and directories
+    #[arg(long)]
+    hidden: bool,
+
+    /// Don't respect .gitignore, .ignore and global git excludes
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// How to treat files that contain NUL bytes
+    #[arg(long, default_value = "skip", value_name = "MODE")]
+    binary: BinaryMode,
+
+    /// Which encoding to transcode files from before searching them. With `auto`, files
+    /// with a UTF-16 byte order mark are transcoded and a UTF-8 byte order mark is skipped.
+    /// Offsets in --json output are then offsets into the UTF-8 text
+    #[arg(short = 'E', long, default_value = "auto", value_name = "ENCODING")]
+    encoding: Encoding,
+
+    /// Search inside .gz, .bz2, .xz and .zst files
+    #[arg(short = 'z', long)]
+    decompress: bool,
+
+    /// Search the files inside .zip, .jar, .tar and .tar.gz archives, printed as
+    /// `archive.tar!/path/inside`
+    #[arg(long, conflicts_with = "write")]
+    search_zip: bool,
+
+    /// Only search the files that the index built by `mygrep index build` can't rule out.
+    /// Files changed since the index was built are always searched
+    #[arg(long)]
+    indexed: bool,
+
+    /// Show line numbers
+    #[arg(short = 'n', long)]
+    line_number: bool,
+
+    /// Show the column of the first match on every line (implies --line-number)
+    #[arg(long)]
+    column: bool,
+
+    /// Show NUM lines after every match
+    #[arg(short = 'A', long, value_name = "NUM")]
+    after_context: Option<usize>,
+
+    /// Show NUM lines before every match
+    #[arg(short = 'B', long, value_name = "NUM")]
+    before_context: Option<usize>,
+
+    /// Show NUM lines before and after every match
+    #[arg(short = 'C', long, value_name = "NUM")]
+    context: Option<usize>,
+
+    /// When to highlight matches, paths and line numbers with colors (see MYGREP_COLORS).
+    /// Without colors, matches are underlined with ^^^
+    #[arg(long, default_value = "auto", value_name = "WHEN")]
+    color: ColorChoice,
+
+    /// Print results as JSON Lines
+    #[arg(long)]
+    json: bool,
+
+    /// Print results as soon as they are found instead of in order
+    #[arg(long)]
+    unordered: bool,
+
+    /// Print results sorted by path once the search is done, the same on every run
+    #[arg(long, overrides_with = "unordered")]
+    sort: bool,
+
+    /// Keep running after the search and search files again as they are created or modified,
+    /// printing only new matches
+    #[arg(long, conflicts_with_all = ["write", "files_without_match", "sort"])]
+    watch: bool,
+
+    /// Don't print errors about files and directories that can't be read
+    #[arg(short = 's', long)]
+    no_messages: bool,
+
+    /// How many files to search at the same time, on every backend [default: number of cores]
+    #[arg(short = 'j', long, value_name = "N", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    threads: Option<usize>,
+
+    /// Don't read .mygreprc or the file MYGREP_CONFIG names
+    #[arg(long)]
+    no_config: bool,
+
+    /// How many MiB of results may wait to be printed before workers pause
+    #[arg(long, default_value_t = 256, value_name = "MIB")]
+    memory_budget: usize,
+
+    #[command(flatten)]
+    #[allow(dead_code)]
+    negations: Negations,
 }
 
-fn main() {
+/// The flags that turn off another flag, so a default from the config file can be undone
+/// on the command line. Whichever of a pair comes last wins.
+#[derive(clap::Args, Debug)]
+#[allow(dead_code)]
+struct Negations {
+    #[arg(long, overrides_with = "ignore_case", hide = true)]
+    no_ignore_case: bool,
 
-    let args: Args = Args::parse();
-    let regex = Regex::new(&args.regex).unwrap();
+    #[arg(long, overrides_with = "smart_case", hide = true)]
+    no_smart_case: bool,
 
-    let paths = if args.paths.is_empty() {
-        vec![std::env::current_dir().unwrap()]
-    } else {
-        args.paths.iter().map(PathBuf::from).collect()
-    };
+    #[arg(long, overrides_with = "hidden", hide = true)]
+    no_hidden: bool,
 
-    match args.kind {
-        Kind::SelfMade => run_self_made(regex, paths),
-        Kind::Rayon => run_rayon(regex, paths),
-        Kind::Tokio => run_tokio(regex, paths)
-    }
-}
+    #[arg(long, overrides_with = "no_ignore", hide = true)]
+    ignore: bool,
 
-fn walk_path(path: PathBuf, tx: &mpsc::Sender<PathBuf>) {
-    if let Ok(metadata) = fs::metadata(&path) {
-        if metadata.is_dir() {
-            if let Ok(entries) = fs::read_dir(&path) {
-                for entry in entries.flatten() {
-                    walk_path(entry.path(), tx);
-                }
-            }
-        } else if metadata.is_file() {
-            let _ = tx.send(path);
-        }
-    }
-}
+    #[arg(long, overrides_with = "follow", hide = true)]
+    no_follow: bool,
 
-fn collect_files(path: &PathBuf, out: &mut Vec<PathBuf>) {
-    if let Ok(metadata) = fs::metadata(path) {
-        if metadata.is_dir() {
-            if let Ok(entries) = fs::read_dir(path) {
-                for entry in entries.flatten() {
-                    collect_files(&entry.path(), out);
-                }
-            }
-        } else if metadata.is_file() {
-            out.push(path.clone());
-        }
-    }
-}
+    #[arg(long, overrides_with = "one_file_system", hide = true)]
+    no_one_file_system: bool,
 
-fn run_self_made(regex: Regex, roots: Vec<PathBuf>) {
-    let regex = Arc::new(regex);
-    let counter = Arc::new(AtomicUsize::new(0));
-
-    let (path_tx, path_rx) = mpsc::channel::<PathBuf>();
-    let (res_tx, res_rx) = mpsc::channel::<GrepResult>();
-    let path_rx = Arc::new(Mutex::new(path_rx));
-    let printer_handle = thread::spawn(move || {
-        let mut next_id: usize = 0;
-        let mut buffer: BTreeMap<usize, GrepResult> = BTreeMap::new();
-
-        while let Ok(res) = res_rx.recv() {
-            buffer.insert(res.search_ctr, res);
-
-            loop {
-                if let Some(r) = buffer.remove(&next_id) {
-                    println!("{}", r);
-                    next_id += 1;
-                } else {
-                    break;
-                }
-            }
-        }
-        while let Some(r) = buffer.remove(&next_id) {
-            println!("{}", r);
-            next_id += 1;
-        }
-    });
+    #[arg(long, overrides_with = "decompress", hide = true)]
+    no_decompress: bool,
 
-    let num_workers = thread::available_parallelism()
-        .map(|n| n.get())
-        .unwrap_or(4);
-
-    let mut worker_handles = Vec::new();
-    for _ in 0..num_workers {
-        let path_rx = Arc::clone(&path_rx);
-        let res_tx = res_tx.clone();
-        let regex = Arc::clone(&regex);
-        let counter = Arc::clone(&counter);
-
-        let handle = thread::spawn(move || loop {
-            let path = {
-                let rx_lock = match path_rx.lock() {
-                    Ok(guard) => guard,
-                    Err(poisoned) => poisoned.into_inner(), 
-                };
-                match rx_lock.recv() {
-                    Ok(p) => p,
-                    Err(_) => break, 
-                }
-            };
+    #[arg(long, overrides_with = "search_zip", hide = true)]
+    no_search_zip: bool,
 
-            let content = match fs::read(&path) {
-                Ok(c) => c,
-                Err(_) => continue, 
-            };
-            let mut ranges = Vec::new();
-            for m in regex.find_iter(&content) {
-                ranges.push(m.start()..m.end());
-            }
-            if !ranges.is_empty() {
-                let id = counter.fetch_add(1, Ordering::SeqCst);
-                let result = GrepResult {
-                    path: path.clone(),
-                    content,
-                    ranges,
-                    search_ctr: id,
-                };
-                if res_tx.send(result).is_err() {
-                    break;
-                }
-            }
-        });
+    #[arg(long, overrides_with = "indexed", hide = true)]
+    no_indexed: bool,
 
-        worker_handles.push(handle);
-    }
-    drop(res_tx);
+    #[arg(long, overrides_with = "line_number", hide = true)]
+    no_line_number: bool,
 
-    for root in roots {
-        walk_path(root, &path_tx);
-    }
-    drop(path_tx);
-    for handle in worker_handles {
-        let _ = handle.join();
-    }
-    let _ = printer_handle.join();
-}
+    #[arg(long, overrides_with = "column", hide = true)]
+    no_column: bool,
 
-fn run_rayon(regex: Regex, roots: Vec<PathBuf>) {
-    use rayon::prelude::*;
+    #[arg(long, overrides_with = "json", hide = true)]
+    no_json: bool,
 
-    let regex = Arc::new(regex);
-    let counter = Arc::new(AtomicUsize::new(0));
+    #[arg(long, overrides_with = "unordered", hide = true)]
+    no_unordered: bool,
 
-    let mut files = Vec::new();
-    for root in &roots {
-        collect_files(root, &mut files);
-    }
+    #[arg(long, overrides_with = "sort", hide = true)]
+    no_sort: bool,
 
-    let (res_tx, res_rx) = mpsc::channel::<GrepResult>();
+    #[arg(long, overrides_with = "no_messages", hide = true)]
+    messages: bool,
+}
 
-    let printer_handle = thread::spawn(move || {
-        let mut next_id: usize = 0;
-        let mut buffer: BTreeMap<usize, GrepResult> = BTreeMap::new();
+/// `mygrep index`, parsed separately so a pattern can't be mistaken for a subcommand.
+#[derive(Parser, Debug)]
+#[command(name = "mygrep index", about = "Manage the trigram index used by --indexed")]
+struct IndexArgs {
+    #[command(subcommand)]
+    command: IndexCommand,
+}
 
-        while let Ok(res) = res_rx.recv() {
-            buffer.insert(res.search_ctr, res);
+#[derive(Subcommand, Debug)]
+enum IndexCommand {
+    /// Build the index of a directory, or bring it up to date. Only files that are new or
+    /// whose modification time or size changed are read again
+    Build {
+        /// The directory to index. The index is stored in it as .mygrep-index
+        #[arg(default_value = ".")]
+        path: PathBuf,
+
+        /// Index hidden files and directories
+        #[arg(long)]
+        hidden: bool,
+
+        /// Don't respect .gitignore, .ignore and global git excludes
+        #[arg(long)]
+        no_ignore: bool,
+    },
+}
 
-            while let Some(r) = buffer.remove(&next_id) {
-                println!("{}", r);
-                next_id += 1;
-            }
-        }
+/// Set by the first Ctrl-C. A second one exits right away.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
-        while let Some(r) = buffer.remove(&next_id) {
-            println!("{}", r);
-            next_id += 1;
-        }
-    });
+fn main() {
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == "index") {
+        index(IndexArgs::parse_from(std::env::args_os().skip(1)));
+    }
 
-    files.par_iter().for_each(|path| {
-        let content = match fs::read(path) {
-            Ok(c) => c,
-            Err(_) => return,
-        };
+    let mut args: Args = Args::parse_from(with_config(std::env::args_os().collect()));
+
+    let walk_options = WalkOptions {
+        hidden: args.hidden,
+        no_ignore: args.no_ignore,
+        globs: std::mem::take(&mut args.globs),
+        excludes: std::mem::take(&mut args.excludes),
+        types: std::mem::take(&mut args.types),
+        types_not: std::mem::take(&mut args.types_not),
+        type_defs: std::mem::take(&mut args.type_defs),
+        max_filesize: args.max_filesize,
+        newer_than: args.newer_than.and_then(|age| SystemTime::now().checked_sub(age)),
+        max_depth: args.max_depth,
+        follow: args.follow,
+        one_file_system: args.one_file_system,
+    };
 
-        let mut ranges = Vec::new();
-        for m in regex.find_iter(&content) {
-            ranges.push(m.start()..m.end());
+    if args.type_list {
+        match walker::type_list(&walk_options) {
+            Ok(types) => {
+                for (name, globs) in types {
+                    println!("{}: {}", name, globs.join(", "));
+                }
+            }
+            Err(e) => {
+                eprintln!("mygrep: {}", e);
+                std::process::exit(2);
+            }
         }
+        return;
+    }
 
-        if ranges.is_empty() {
-            return;
+    // With -e the patterns come from the flags, so the first positional is a path.
+    let patterns = if args.patterns.is_empty() {
+        args.regex.take().into_iter().collect()
+    } else {
+        if let Some(path) = args.regex.take() {
+            args.paths.insert(0, path);
         }
-
-        let id = counter.fetch_add(1, Ordering::SeqCst);
-        let result = GrepResult {
-            path: path.clone(),
-            content,
-            ranges,
-            search_ctr: id,
-        };
-        let _ = res_tx.send(result);
-    });
-    drop(res_tx);
-    let _ = printer_handle.join();
-}
-
-fn run_tokio(regex: Regex, roots: Vec<PathBuf>) {
-    let runtime = match tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-    {
-        Ok(rt) => rt,
+        std::mem::take(&mut args.patterns)
+    };
+    let match_options = MatchOptions {
+        ignore_case: args.ignore_case,
+        smart_case: args.smart_case,
+        word: args.word_regexp,
+        line: args.line_regexp,
+        fixed_strings: args.fixed_strings,
+        multiline: args.multiline,
+        engine: if args.pcre2 {
+            Engine::Pcre2
+        } else {
+            Engine::Default
+        },
+        no_prefilter: false,
+    };
+    let regex = match matcher::build(&patterns, &match_options) {
+        Ok(regex) => regex,
         Err(e) => {
-            eprintln!("Failed to build Tokio runtime: {}", e);
-            return;
+            eprintln!("mygrep: {}", e);
+            std::process::exit(2);
         }
     };
 
-    runtime.block_on(async_run_tokio(regex, roots));
-}
+    let paths = if args.paths.is_empty() && stdin_is_searchable() {
+        vec![PathBuf::from(search::STDIN)]
+    } else if args.paths.is_empty() {
+        vec![std::env::current_dir().unwrap()]
+    } else {
+        args.paths.iter().map(PathBuf::from).collect()
+    };
 
-async fn async_run_tokio(regex: Regex, roots: Vec<PathBuf>) {
-    use tokio::sync::mpsc;
-    use tokio::task;
+    let mode = if args.quiet {
+        OutputMode::Quiet
+    } else if args.files_with_matches {
+        OutputMode::FilesWithMatches
+    } else if args.files_without_match {
+        OutputMode::FilesWithoutMatch
+    } else if args.count {
+        OutputMode::Count
+    } else {
+        OutputMode::Matches
+    };
+    // Context lines are only printed along with the matches themselves.
+    let context = |n: Option<usize>| match mode {
+        OutputMode::Matches => n.or(args.context).unwrap_or(0),
+        _ => 0,
+    };
 
-    let regex = Arc::new(regex);
-    let counter = Arc::new(AtomicUsize::new(0));
+    let output = OutputOptions {
+        mode,
+        line_number: args.line_number,
+        column: args.column,
+        before_context: context(args.before_context),
+        after_context: context(args.after_context),
+        json: args.json,
+        diff: args.diff,
+        // Machine readable formats are never colored.
+        colors: (!args.json && !args.diff && args.color.enabled()).then(Colors::from_env),
+    };
 
-    let mut files = Vec::new();
-    for root in &roots {
-        collect_files(root, &mut files);
+    // The first Ctrl-C stops the search but still prints what is ready to be printed.
+    let cancel = CancelToken::new();
+    let handler_cancel = cancel.clone();
+    let handler = ctrlc::set_handler(move || {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        handler_cancel.cancel();
+    });
+    if let Err(e) = handler {
+        eprintln!("mygrep: {}", e);
     }
 
-    let (res_tx, mut res_rx) = mpsc::unbounded_channel::<GrepResult>();
-
-    let printer = task::spawn(async move {
-        let mut next_id: usize = 0;
-        let mut buffer: BTreeMap<usize, GrepResult> = BTreeMap::new();
-
-        while let Some(res) = res_rx.recv().await {
-            buffer.insert(res.search_ctr, res);
+    let search = SearchOptions {
+        binary: args.binary,
+        before_context: output.before_context,
+        after_context: output.after_context,
+        decompress: args.decompress,
+        invert: args.invert_match,
+        files_without_match: args.files_without_match,
+        replace: args.replace.map(String::into_bytes),
+        write: args.write,
+        multiline: args.multiline,
+        max_count: args.max_count,
+        cancel: cancel.clone(),
+        encoding: args.encoding,
+        search_zip: args.search_zip,
+        searched: None,
+    };
 
-            while let Some(r) = buffer.remove(&next_id) {
-                println!("{}", r);
-                next_id += 1;
+    let candidates = args.indexed.then(|| {
+        let dir = paths
+            .iter()
+            .find(|p| p.is_dir())
+            .cloned()
+            .unwrap_or_else(|| std::env::current_dir().unwrap());
+        match Index::find(&dir) {
+            Ok(index) => Some(Candidates::new(index, &patterns, &match_options, &search)),
+            Err(e) => {
+                if !args.no_messages {
+                    eprintln!("mygrep: {}, searching every file", e);
+                }
+                None
             }
         }
+    });
 
-        while let Some(r) = buffer.remove(&next_id) {
-            println!("{}", r);
-            next_id += 1;
+    let searcher = Searcher::builder(regex)
+        .walk_options(walk_options)
+        .search_options(search)
+        .kind(args.kind)
+        .unordered(args.unordered)
+        .sorted(args.sort)
+        .memory_budget(args.memory_budget.saturating_mul(1024 * 1024))
+        .no_messages(args.no_messages)
+        .candidates(candidates.flatten())
+        .threads(args.threads)
+        .cancel_token(cancel)
+        // With -q the first match decides the exit code.
+        .max_total(if args.quiet { Some(1) } else { args.max_total })
+        .build();
+
+    // Like grep: 0 if something was reported, 1 if nothing was, 2 if an error occurred.
+    if args.watch {
+        if let Err(e) = searcher.watch(&paths, Printer::new(output)) {
+            eprintln!("mygrep: {}", e);
+            std::process::exit(2);
         }
-    });
+        std::process::exit(130);
+    }
 
-    let mut handles = Vec::new();
-    for path in files {
-        let regex = Arc::clone(&regex);
-        let counter = Arc::clone(&counter);
-        let res_tx = res_tx.clone();
+    let code = match searcher.run(&paths, Printer::new(output)) {
+        _ if INTERRUPTED.load(Ordering::SeqCst) => 130,
+        // Like grep -q, a match wins over errors in files that were skipped.
+        Ok(stats) if args.quiet && stats.results > 0 => 0,
+        Ok(stats) if stats.errors > 0 => 2,
+        Ok(stats) if stats.results > 0 => 0,
+        Ok(_) => 1,
+        Err(e) => {
+            eprintln!("mygrep: {}", e);
+            2
+        }
+    };
+    std::process::exit(code);
+}
 
-        let handle = task::spawn(async move {
-            let content = match tokio::fs::read(&path).await {
-                Ok(c) => c,
-                Err(_) => return,
-            };
+/// `argv` with the arguments from the config file put right after the program name,
+/// unless `--no-config` is given.
+fn with_config(mut argv: Vec<OsString>) -> Vec<OsString> {
+    if argv.iter().skip(1).any(|arg| arg == "--no-config") {
+        return argv;
+    }
+    let Some(path) = config::find() else {
+        return argv;
+    };
+    match config::load(&path).map_err(|e| e.to_string()).and_then(|defaults| {
+        check_overridable(&defaults)?;
+        Ok(defaults)
+    }) {
+        Ok(defaults) => {
+            argv.splice(1..1, defaults);
+        }
+        Err(e) => {
+            eprintln!("mygrep: {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+    }
+    argv
+}
 
-            let mut ranges = Vec::new();
-            for m in regex.find_iter(&content) {
-                ranges.push(m.start()..m.end());
-            }
+/// Options that only make sense for a single search, since the command line can't unset
+/// them again, and that get in the way of other flags while they are set.
+const COMMAND_LINE_ONLY: [&str; 4] = ["regexp", "replace", "max-count", "max-total"];
+
+/// Fail on the flags in `defaults` that the command line couldn't undo: the on/off flags
+/// that have no `--no-` form, and the options in `COMMAND_LINE_ONLY`.
+fn check_overridable(defaults: &[OsString]) -> Result<(), String> {
+    let command = Args::command();
+    let by_long = |name: &str| command.get_arguments().find(|a| a.get_long() == Some(name));
+    let check = |arg: &clap::Arg| {
+        let Some(name) = arg.get_long() else {
+            return Ok(());
+        };
+        let negation = match name.strip_prefix("no-") {
+            Some(positive) => positive.to_string(),
+            None => format!("no-{}", name),
+        };
+        let overridable = !COMMAND_LINE_ONLY.contains(&name)
+            && (!matches!(arg.get_action(), ArgAction::SetTrue) || by_long(&negation).is_some());
+        if overridable {
+            return Ok(());
+        }
+        Err(format!(
+            "--{} can't be set in the config file, since it can't be undone on the command line",
+            name
+        ))
+    };
 
-            if ranges.is_empty() {
-                return;
+    // `config::load` attaches every value to its flag, so each default is one flag.
+    for default in defaults.iter().filter_map(|d| d.to_str()) {
+        if let Some(long) = default.strip_prefix("--") {
+            let name = long.split_once('=').map_or(long, |(name, _)| name);
+            if let Some(arg) = by_long(name) {
+                check(arg)?;
+            }
+        } else if let Some(shorts) = default.strip_prefix('-') {
+            // A cluster such as `-nj4` ends at the first flag that takes a value.
+            for c in shorts.chars() {
+                match command.get_arguments().find(|a| a.get_short() == Some(c)) {
+                    Some(arg) if arg.get_action().takes_values() => {
+                        check(arg)?;
+                        break;
+                    }
+                    Some(arg) => check(arg)?,
+                    None => break,
+                }
             }
+        }
+    }
+    Ok(())
+}
 
-            let id = counter.fetch_add(1, Ordering::SeqCst);
-            let result = GrepResult {
-                path: path.clone(),
-                content,
-                ranges,
-                search_ctr: id,
+/// Run `mygrep index` and exit.
+fn index(args: IndexArgs) -> ! {
+    match args.command {
+        IndexCommand::Build {
+            path,
+            hidden,
+            no_ignore,
+        } => {
+            let walk_options = WalkOptions {
+                hidden,
+                no_ignore,
+                ..WalkOptions::default()
             };
+            match Index::build(&path, &walk_options) {
+                Ok(summary) => {
+                    println!(
+                        "indexed {} files ({} updated, {} removed)",
+                        summary.files, summary.updated, summary.removed
+                    );
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("mygrep: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+    }
+}
+
+/// Whether stdin is a pipe or a redirected file, as opposed to a terminal or `/dev/null`.
+fn stdin_is_searchable() -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        std::fs::metadata("/dev/stdin")
+            .is_ok_and(|m| m.is_file() || m.file_type().is_fifo() || m.file_type().is_socket())
+    }
+    #[cfg(not(unix))]
+    {
+        use std::io::IsTerminal;
+        !std::io::stdin().is_terminal()
+    }
+}
 
-            let _ = res_tx.send(result);
-        });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        handles.push(handle);
+    fn check(defaults: &[&str]) -> Result<(), String> {
+        let defaults: Vec<OsString> = defaults.iter().map(OsString::from).collect();
+        check_overridable(&defaults)
     }
 
-    drop(res_tx);
-    for handle in handles {
-        let _ = handle.await;
+    #[test]
+    fn config_accepts_what_the_command_line_can_undo() {
+        let defaults = [
+            "-j4",
+            "--kind=rayon",
+            "--hidden",
+            "--no-ignore",
+            "-nS",
+            "--exclude=-n",
+            "-C2",
+            "--sort",
+        ];
+        assert_eq!(check(&defaults), Ok(()));
     }
-    let _ = printer.await;
-}
 
+    #[test]
+    fn config_rejects_what_the_command_line_cannot_undo() {
+        for (default, name) in [
+            ("-w", "word-regexp"),
+            ("-nw", "word-regexp"),
+            ("--write", "write"),
+            ("-ehit", "regexp"),
+            ("--regexp=hit", "regexp"),
+            ("-rX", "replace"),
+            ("-nrX", "replace"),
+            ("--replace=X", "replace"),
+            ("-m2", "max-count"),
+            ("--max-total=3", "max-total"),
+        ] {
+            let err = check(&[default]).unwrap_err();
+            assert!(err.starts_with(&format!("--{} ", name)), "{}: {}", default, err);
+        }
+    }
 
+    #[test]
+    fn config_defaults_are_overridden_by_the_command_line() {
+        let argv = [
+            "mygrep",
+            "--json",
+            "--hidden",
+            "-j4",
+            "--no-json",
+            "--no-hidden",
+            "-j2",
+            "x",
+        ];
+        let args = Args::try_parse_from(argv).unwrap();
+        assert!(!args.json);
+        assert!(!args.hidden);
+        assert_eq!(args.threads, Some(2));
+    }
 
+    #[test]
+    fn write_needs_files_it_can_write_back() {
+        assert!(Args::try_parse_from(["mygrep", "-r", "y", "--write", "-z", "x"]).is_err());
+        assert!(Args::try_parse_from(["mygrep", "--write", "x"]).is_err());
+        assert!(Args::try_parse_from(["mygrep", "-r", "y", "--write", "x"]).is_ok());
+    }
+
+    #[test]
+    fn max_total_must_be_positive() {
+        assert!(Args::try_parse_from(["mygrep", "--max-total", "0", "x"]).is_err());
+        let args = Args::try_parse_from(["mygrep", "--max-total", "1", "x"]).unwrap();
+        assert_eq!(args.max_total, Some(1));
+    }
+}
@@ -0,0 +1,288 @@
+use memchr::{memchr, memrchr};
+use regex::bytes::{Regex, RegexBuilder};
+use std::ops::Range;
+
+use crate::prefilter::Prefilter;
+use crate::Error;
+
+/// A compiled pattern. Everything that searches goes through this trait, so the backends
+/// and the search code don't care which regex engine is behind it.
+pub trait Matcher: Send + Sync {
+    /// The leftmost match in `haystack` that starts at or after `start`. Anchors and
+    /// look-around still see the bytes before `start`.
+    fn find_at(&self, haystack: &[u8], start: usize) -> Option<Range<usize>>;
+
+    /// Append `template` to `dst`, with `$1`, `${name}` and `$$` replaced like `Regex::replace`
+    /// does, using the capture groups of the match that starts at `start`.
+    fn expand_at(&self, haystack: &[u8], start: usize, template: &[u8], dst: &mut Vec<u8>);
+}
+
+impl dyn Matcher + '_ {
+    /// Every non-overlapping match in `haystack`, left to right. An empty match right
+    /// after the previous match is skipped, the same way `Regex::find_iter` does.
+    pub fn find_iter<'h>(&'h self, haystack: &'h [u8]) -> impl Iterator<Item = Range<usize>> + 'h {
+        let mut start = 0;
+        let mut last_end = None;
+        std::iter::from_fn(move || loop {
+            if start > haystack.len() {
+                return None;
+            }
+            let m = self.find_at(haystack, start)?;
+            if m.is_empty() && Some(m.end) == last_end {
+                start = m.end + 1;
+                continue;
+            }
+            start = if m.is_empty() { m.end + 1 } else { m.end };
+            last_end = Some(m.end);
+            return Some(m);
+        })
+    }
+}
+
+impl Matcher for Regex {
+    fn find_at(&self, haystack: &[u8], start: usize) -> Option<Range<usize>> {
+        Regex::find_at(self, haystack, start).map(|m| m.range())
+    }
+
+    fn expand_at(&self, haystack: &[u8], start: usize, template: &[u8], dst: &mut Vec<u8>) {
+        if let Some(caps) = self.captures_at(haystack, start) {
+            caps.expand(template, dst);
+        }
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for Box<M> {
+    fn find_at(&self, haystack: &[u8], start: usize) -> Option<Range<usize>> {
+        (**self).find_at(haystack, start)
+    }
+
+    fn expand_at(&self, haystack: &[u8], start: usize, template: &[u8], dst: &mut Vec<u8>) {
+        (**self).expand_at(haystack, start, template, dst)
+    }
+}
+
+/// Keeps the matches of another matcher within a single line, like grep does: a match
+/// that would run into the next line is searched for again within its own line.
+/// With a prefilter, the matcher only runs on the lines that contain one of its literals.
+/// It sees everything up to and including the line terminator, so anchors and word
+/// boundaries match the same as without it.
+struct LineMatcher<M> {
+    matcher: M,
+    prefilter: Option<Prefilter>,
+}
+
+impl<M: Matcher> LineMatcher<M> {
+    /// The bounds of the line that contains byte `pos`, without its line terminator.
+    fn line(haystack: &[u8], pos: usize) -> Range<usize> {
+        let start = memrchr(b'\n', &haystack[..pos]).map_or(0, |i| i + 1);
+        let end = memchr(b'\n', &haystack[pos..]).map_or(haystack.len(), |i| pos + i);
+        start..end
+    }
+}
+
+impl<M: Matcher> Matcher for LineMatcher<M> {
+    fn find_at(&self, haystack: &[u8], mut start: usize) -> Option<Range<usize>> {
+        loop {
+            let mut searched = haystack;
+            if let Some(prefilter) = &self.prefilter {
+                let line = Self::line(haystack, prefilter.find(haystack, start)?);
+                searched = &haystack[..haystack.len().min(line.end + 1)];
+                start = start.max(line.start);
+            }
+            let Some(m) = self.matcher.find_at(searched, start) else {
+                if searched.len() == haystack.len() {
+                    return None;
+                }
+                start = searched.len();
+                continue;
+            };
+            if memchr(b'\n', &haystack[m.clone()]).is_none() {
+                return Some(m);
+            }
+            let line = Self::line(haystack, m.start);
+            if let Some(inner) = self
+                .matcher
+                .find_at(&haystack[line.clone()], m.start - line.start)
+            {
+                return Some(inner.start + line.start..inner.end + line.start);
+            }
+            if line.end == haystack.len() {
+                return None;
+            }
+            start = line.end + 1;
+        }
+    }
+
+    fn expand_at(&self, haystack: &[u8], start: usize, template: &[u8], dst: &mut Vec<u8>) {
+        let line = Self::line(haystack, start);
+        self.matcher
+            .expand_at(&haystack[line.clone()], start - line.start, template, dst);
+    }
+}
+
+/// The regex engine that compiles the patterns.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// The `regex` crate: linear time, no look-around or backreferences
+    #[default]
+    Default,
+    /// PCRE2, only available with the `pcre2` cargo feature
+    Pcre2,
+}
+
+/// grep-style options that change what counts as a match.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MatchOptions {
+    /// Match case insensitively
+    pub ignore_case: bool,
+
+    /// Match case insensitively, unless a pattern contains an uppercase letter
+    pub smart_case: bool,
+
+    /// Only match whole words
+    pub word: bool,
+
+    /// Only match whole lines
+    pub line: bool,
+
+    /// Treat the patterns as literal strings instead of regular expressions
+    pub fixed_strings: bool,
+
+    /// Let matches span several lines. Otherwise every match lies within a single line
+    pub multiline: bool,
+
+    /// Which engine compiles the patterns
+    pub engine: Engine,
+
+    /// Run the regex over everything, instead of only on the lines that contain a literal
+    /// every match needs
+    pub no_prefilter: bool,
+}
+
+/// Compile `patterns` into a single matcher that matches wherever any of them matches.
+pub fn build(patterns: &[String], options: &MatchOptions) -> Result<Box<dyn Matcher>, Error> {
+    let (pattern, ignore_case) = combine(patterns, options);
+    let prefilter = match options.engine {
+        Engine::Default if !options.no_prefilter => Prefilter::new(&pattern, ignore_case),
+        _ => None,
+    };
+    let matcher: Box<dyn Matcher> = match options.engine {
+        Engine::Default => Box::new(
+            RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                // `^` and `$` match at the start and end of every line, even when matches
+                // may span lines.
+                .multi_line(true)
+                .crlf(true)
+                .build()
+                .map_err(|err| {
+                    // Point at the pattern the user wrote rather than at the combined one.
+                    patterns
+                        .iter()
+                        .filter(|_| !options.fixed_strings)
+                        .find_map(|p| Regex::new(p).err())
+                        .unwrap_or(err)
+                })?,
+        ),
+        #[cfg(feature = "pcre2")]
+        Engine::Pcre2 => Box::new(crate::pcre::build(&pattern, ignore_case).map_err(|err| {
+            patterns
+                .iter()
+                .filter(|_| !options.fixed_strings)
+                .find_map(|p| crate::pcre::build(p, false).err())
+                .unwrap_or(err)
+        })?),
+        #[cfg(not(feature = "pcre2"))]
+        Engine::Pcre2 => {
+            return Err(Error::Unsupported(
+                "PCRE2 support is not compiled in, rebuild with `--features pcre2`",
+            ))
+        }
+    };
+    if options.multiline {
+        Ok(matcher)
+    } else {
+        Ok(Box::new(LineMatcher { matcher, prefilter }))
+    }
+}
+
+/// The single pattern that `patterns` are combined into, with `options` applied, and
+/// whether it matches case insensitively.
+pub(crate) fn combine(patterns: &[String], options: &MatchOptions) -> (String, bool) {
+    let alternatives: Vec<String> = patterns
+        .iter()
+        .map(|p| {
+            if options.fixed_strings {
+                regex::escape(p)
+            } else {
+                p.clone()
+            }
+        })
+        .map(|p| format!("(?:{})", p))
+        .collect();
+    let mut pattern = alternatives.join("|");
+
+    if options.line {
+        pattern = format!("(?m:^(?:{})$)", pattern);
+    } else if options.word {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+
+    let ignore_case = options.ignore_case
+        || (options.smart_case && !patterns.iter().any(|p| p.chars().any(char::is_uppercase)));
+    (pattern, ignore_case)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The start and end of every match of `pattern` in `haystack`.
+    fn find(pattern: &str, haystack: &str, options: &MatchOptions) -> Vec<(usize, usize)> {
+        let matcher = build(&[pattern.to_string()], options).unwrap();
+        matcher
+            .find_iter(haystack.as_bytes())
+            .map(|r| (r.start, r.end))
+            .collect()
+    }
+
+    fn anchors_match_every_line(engine: Engine) {
+        let haystack = "foo\r\nbar\nfoo bar\n";
+        for multiline in [false, true] {
+            let options = MatchOptions {
+                multiline,
+                engine,
+                ..MatchOptions::default()
+            };
+            assert_eq!(find("^bar", haystack, &options), [(5, 8)], "{:?}", options);
+            assert_eq!(find("foo$", haystack, &options), [(0, 3)], "{:?}", options);
+            assert_eq!(
+                find("bar$", haystack, &options),
+                [(5, 8), (13, 16)],
+                "{:?}",
+                options
+            );
+        }
+    }
+
+    #[test]
+    fn anchors_match_every_line_with_or_without_multiline() {
+        anchors_match_every_line(Engine::Default);
+    }
+
+    #[cfg(feature = "pcre2")]
+    #[test]
+    fn pcre2_anchors_match_every_line_with_or_without_multiline() {
+        anchors_match_every_line(Engine::Pcre2);
+    }
+
+    #[test]
+    fn multiline_matches_span_lines() {
+        let options = MatchOptions {
+            multiline: true,
+            ..MatchOptions::default()
+        };
+        assert_eq!(find(r"foo\nbar", "foo\nbar\n", &options), [(0, 7)]);
+        assert!(find(r"foo\nbar", "foo\nbar\n", &MatchOptions::default()).is_empty());
+    }
+}
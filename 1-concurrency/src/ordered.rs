@@ -0,0 +1,157 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+
+use crate::budget::Budget;
+use crate::cancel::CancelToken;
+use crate::diagnostics::Diagnostics;
+use crate::grep_result::GrepResult;
+use crate::reorder::ReorderBuffer;
+use crate::search::Found;
+use crate::searcher::Sink;
+
+/// The worker side of the path from the workers to the sink, shared by every backend.
+/// Turns what a worker found into a numbered result, after making room for it in the budget.
+pub struct Producer {
+    counter: AtomicUsize,
+    budget: Arc<Budget>,
+    diagnostics: Arc<Diagnostics>,
+    cancel: CancelToken,
+}
+
+impl Producer {
+    pub fn new(budget: Arc<Budget>, diagnostics: Arc<Diagnostics>, cancel: CancelToken) -> Self {
+        Self {
+            counter: AtomicUsize::new(0),
+            budget,
+            diagnostics,
+            cancel,
+        }
+    }
+
+    /// Whether the search was cancelled, so workers should not start on another file.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// The result to send for `path`, if there is one to report. Errors are reported instead.
+    /// Blocks while the budget is full.
+    pub fn result(&self, path: PathBuf, found: io::Result<Option<Found>>) -> Option<GrepResult> {
+        let result = self.unnumbered(path, found)?;
+        self.budget.acquire(result.memory_size());
+        Some(self.number(result))
+    }
+
+    /// Like `result`, but waits for the budget asynchronously.
+    pub async fn result_async(
+        &self,
+        path: PathBuf,
+        found: io::Result<Option<Found>>,
+    ) -> Option<GrepResult> {
+        let result = self.unnumbered(path, found)?;
+        self.budget.acquire_async(result.memory_size()).await;
+        Some(self.number(result))
+    }
+
+    fn unnumbered(&self, path: PathBuf, found: io::Result<Option<Found>>) -> Option<GrepResult> {
+        if self.is_cancelled() {
+            return None;
+        }
+        match found {
+            Ok(found) => Some(found?.into_result(path, 0)),
+            Err(e) => {
+                self.diagnostics.file_error(&path, e);
+                None
+            }
+        }
+    }
+
+    /// Results only get a `search_ctr` once they hold their room in the budget, so the
+    /// result the sink is waiting for next can never be the one stuck on the budget.
+    fn number(&self, mut result: GrepResult) -> GrepResult {
+        result.search_ctr = self.counter.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+}
+
+/// The sink side: puts results back in `search_ctr` order, gives their memory back to the
+/// budget and hands them to the sink, then finishes the sink once the workers are done.
+/// Once `max_total` matching lines were passed on, it cancels the search. After
+/// cancelling, results that weren't passed on yet are dropped.
+pub struct OrderedSink<S> {
+    sink: S,
+    reorder: ReorderBuffer,
+    budget: Arc<Budget>,
+    cancel: CancelToken,
+    max_total: Option<usize>,
+    total: usize,
+}
+
+impl<S: Sink> OrderedSink<S> {
+    pub fn new(
+        sink: S,
+        unordered: bool,
+        budget: Arc<Budget>,
+        cancel: CancelToken,
+        max_total: Option<usize>,
+    ) -> Self {
+        Self {
+            sink,
+            reorder: ReorderBuffer::new(unordered),
+            budget,
+            cancel,
+            max_total,
+            total: 0,
+        }
+    }
+
+    /// Receive results until every sender is gone.
+    pub fn run(mut self, rx: mpsc::Receiver<GrepResult>) {
+        while let Ok(result) = rx.recv() {
+            self.push(result);
+        }
+        self.finish();
+    }
+
+    /// Like `run`, for a tokio channel.
+    pub async fn run_async(mut self, mut rx: tokio::sync::mpsc::Receiver<GrepResult>) {
+        while let Some(result) = rx.recv().await {
+            self.push(result);
+        }
+        self.finish();
+    }
+
+    fn push(&mut self, result: GrepResult) {
+        if self.cancel.is_cancelled() {
+            self.budget.release(result.memory_size());
+            return;
+        }
+        self.reorder.push(result);
+        self.flush();
+    }
+
+    /// Pass on every result whose turn it is.
+    fn flush(&mut self) {
+        while let Some(mut result) = self.reorder.pop() {
+            self.budget.release(result.memory_size());
+            if self.cancel.is_cancelled() {
+                continue;
+            }
+            if let Some(max) = self.max_total {
+                result.truncate_matches(max - self.total);
+                // A file listed by `-L` has no matching lines but still counts as one.
+                self.total += result.matched_lines().max(1);
+                if self.total >= max {
+                    self.cancel.cancel();
+                }
+            }
+            self.sink.result(result);
+        }
+    }
+
+    fn finish(mut self) {
+        self.flush();
+        self.sink.finish();
+    }
+}
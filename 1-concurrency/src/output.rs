@@ -0,0 +1,465 @@
+use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
+
+use crate::color::{Colors, Styled};
+use crate::grep_result::{self, GrepResult, Line};
+use crate::json;
+use crate::search::count_lines;
+use crate::searcher::Sink;
+
+/// What is printed for every reported file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// The matches themselves
+    #[default]
+    Matches,
+    /// Only the path of every file that matches (`-l`)
+    FilesWithMatches,
+    /// Only the path of every file that doesn't match (`-L`)
+    FilesWithoutMatch,
+    /// The path and the number of matching lines of every file that matches (`-c`)
+    Count,
+    /// Nothing at all, only the exit code tells whether something matched (`-q`)
+    Quiet,
+}
+
+/// Options that control how a `GrepResult` is printed.
+/// When none of them is set, the classic `^^^` underline format is used.
+#[derive(Debug, Default, Clone)]
+pub struct OutputOptions {
+    /// What to print for every file
+    pub mode: OutputMode,
+
+    /// Prefix every printed line with its 1-based line number
+    pub line_number: bool,
+
+    /// Prefix every matching line with the 1-based byte column of its first match
+    pub column: bool,
+
+    /// Number of lines to print before every matching line
+    pub before_context: usize,
+
+    /// Number of lines to print after every matching line
+    pub after_context: usize,
+
+    /// Print JSON Lines records instead of human readable text
+    pub json: bool,
+
+    /// Print the replacements as a unified diff instead of a preview
+    pub diff: bool,
+
+    /// Highlight matches inline with these colors instead of underlining them
+    pub colors: Option<Colors>,
+}
+
+impl OutputOptions {
+    /// Whether results should be printed line by line, grep style.
+    pub fn is_line_oriented(&self) -> bool {
+        self.line_number
+            || self.column
+            || self.before_context > 0
+            || self.after_context > 0
+            || self.colors.is_some()
+    }
+
+    /// Write `value` in `style` if coloring is on, as is otherwise.
+    fn paint(
+        &self,
+        f: &mut impl std::fmt::Write,
+        style: impl Fn(&Colors) -> &str,
+        value: impl Display,
+    ) -> std::fmt::Result {
+        match &self.colors {
+            Some(colors) => write!(f, "{}", Styled(style(colors), value)),
+            None => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Prints results in the order it receives them and keeps totals for the closing summary.
+/// Every result is written in one piece and flushed right away, so output shows up as
+/// soon as a file is done, and results from different files never interleave.
+pub struct Printer<W: Write = io::Stdout> {
+    options: OutputOptions,
+    out: W,
+    /// Set once writing failed, e.g. because the reading end of a pipe was closed.
+    /// Nothing is written after that.
+    failed: bool,
+    files: usize,
+    matches: usize,
+}
+
+impl Printer {
+    /// A printer that writes to stdout.
+    pub fn new(options: OutputOptions) -> Self {
+        Self::with_writer(options, io::stdout())
+    }
+}
+
+impl<W: Write> Printer<W> {
+    /// A printer that writes to `out`.
+    pub fn with_writer(options: OutputOptions, out: W) -> Self {
+        Self {
+            options,
+            out,
+            failed: false,
+            files: 0,
+            matches: 0,
+        }
+    }
+
+    /// Print a single result.
+    pub fn print(&mut self, result: &GrepResult) {
+        self.files += 1;
+        self.matches += result.ranges.len();
+        let text = self.format(result);
+        self.write(&text);
+    }
+
+    /// Everything that is printed for `result`, in the configured output format.
+    fn format(&self, result: &GrepResult) -> String {
+        match self.options.mode {
+            OutputMode::Matches => {}
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => {
+                return format!("{}\n", self.path(result));
+            }
+            OutputMode::Count => {
+                return format!("{}:{}\n", self.path(result), result.matched_lines());
+            }
+            OutputMode::Quiet => return String::new(),
+        }
+        if self.options.json || self.options.diff {
+            result.display(&self.options).to_string()
+        } else {
+            format!("{}\n", result.display(&self.options))
+        }
+    }
+
+    /// The path of `result`, colored if coloring is on.
+    fn path(&self, result: &GrepResult) -> String {
+        let mut path = String::new();
+        let _ = self
+            .options
+            .paint(&mut path, |c| &c.path, result.path.display());
+        path
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.failed {
+            return;
+        }
+        let written = self.out.write_all(text.as_bytes());
+        self.failed = written.and_then(|_| self.out.flush()).is_err();
+    }
+
+    /// Print whatever has to come after the last result.
+    pub fn finish(&mut self) {
+        if self.options.json && self.options.mode != OutputMode::Quiet {
+            let summary = format!("{}\n", json::summary(self.files, self.matches));
+            self.write(&summary);
+        }
+    }
+
+    /// The writer the printer writes to.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Sink for Printer<W> {
+    fn result(&mut self, result: GrepResult) {
+        self.print(&result);
+    }
+
+    fn finish(&mut self) {
+        Printer::finish(self);
+    }
+}
+
+/// A `GrepResult` paired with the options it should be printed with.
+pub struct ResultDisplay<'a> {
+    result: &'a GrepResult,
+    options: &'a OutputOptions,
+}
+
+impl GrepResult {
+    /// Display this result using `options` instead of the default underline format.
+    pub fn display<'a>(&'a self, options: &'a OutputOptions) -> ResultDisplay<'a> {
+        ResultDisplay {
+            result: self,
+            options,
+        }
+    }
+}
+
+impl Display for ResultDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let result = self.result;
+        if self.options.json {
+            return json::write_result(result, f);
+        }
+        if self.options.diff {
+            return write_diff(result, f);
+        }
+        if !self.options.is_line_oriented() || result.binary || result.ranges.is_empty() {
+            return Display::fmt(result, f);
+        }
+
+        let options = self.options;
+        write!(f, ">>> (#{}) ", result.search_ctr)?;
+        options.paint(f, |c| &c.path, format_args!("{:?}", result.path))?;
+        writeln!(f)?;
+        let joined;
+        let lines = if result.replacements.is_empty() {
+            &result.lines
+        } else {
+            joined = joined_lines(result);
+            &joined
+        };
+        let mut previous: Option<usize> = None;
+        for line in lines {
+            // Lines that don't follow each other belong to different blocks.
+            if previous.is_some_and(|number| number + 1 != line.number) {
+                writeln!(f, "--")?;
+            }
+            // A joined line ends on the line of its last newline.
+            previous = Some(line.number + count_lines(&line.text));
+
+            let column = result.match_column(line);
+            let sep = if column.is_some() { ':' } else { '-' };
+            if options.line_number || options.column {
+                options.paint(f, |c| &c.line, line.number + 1)?;
+                write!(f, "{}", sep)?;
+            }
+            if let (true, Some(col)) = (options.column, column) {
+                options.paint(f, |c| &c.column, col)?;
+                write!(f, "{}", sep)?;
+            }
+            match &options.colors {
+                Some(colors) => write_highlighted(result, line, &colors.matched, f)?,
+                None => {
+                    let text = result.replaced(line.offset, &line.text);
+                    write!(f, "{}", String::from_utf8_lossy(&text))?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The lines of `result` the way `--write` leaves them: lines that a match runs across
+/// are joined into one with the terminators between them, numbered like the first of them,
+/// so the replacement takes their place.
+fn joined_lines(result: &GrepResult) -> Vec<Line> {
+    let mut joined: Vec<Line> = Vec::new();
+    for line in &result.lines {
+        let crossed = |last: &&mut Line| {
+            let i = result.ranges.partition_point(|r| r.end <= last.end());
+            last.number + count_lines(&last.text) + 1 == line.number
+                && result.ranges.get(i).is_some_and(|r| r.start <= last.end())
+        };
+        match joined.last_mut().filter(crossed) {
+            Some(last) => {
+                let terminator = last.terminator(line);
+                last.text.extend_from_slice(terminator);
+                last.text.extend_from_slice(&line.text);
+            }
+            None => joined.push(Line {
+                number: line.number,
+                offset: line.offset,
+                text: line.text.clone(),
+            }),
+        }
+    }
+    joined
+}
+
+/// Write `line` with every match on it, or its replacement, in the `matched` style.
+fn write_highlighted(
+    result: &GrepResult,
+    line: &Line,
+    matched: &str,
+    f: &mut Formatter<'_>,
+) -> std::fmt::Result {
+    let text = |from: usize, to: usize| {
+        String::from_utf8_lossy(&line.text[from - line.offset..to - line.offset])
+    };
+    let mut pos = line.offset;
+    let first = result.ranges.partition_point(|r| r.end <= line.offset);
+    for (i, range) in result.ranges.iter().enumerate().skip(first) {
+        if range.start > line.end() {
+            break;
+        }
+        let start = range.start.clamp(pos, line.end());
+        let end = range.end.clamp(start, line.end());
+        write!(f, "{}", text(pos, start))?;
+        match result.replacements.get(i) {
+            // A match that started on an earlier line was replaced there.
+            Some(_) if range.start < line.offset => {}
+            Some(replacement) => write!(
+                f,
+                "{}",
+                Styled(matched, String::from_utf8_lossy(replacement))
+            )?,
+            None if start < end => write!(f, "{}", Styled(matched, text(start, end)))?,
+            None => {}
+        }
+        pos = end;
+    }
+    write!(f, "{}", text(pos, line.end()))
+}
+
+/// Write the replacements in `result` as a unified diff without context lines,
+/// with one hunk for every run of consecutive matching lines.
+fn write_diff(result: &GrepResult, f: &mut Formatter<'_>) -> std::fmt::Result {
+    if result.binary || result.replacements.is_empty() {
+        return Ok(());
+    }
+
+    let matched: Vec<_> = result
+        .lines
+        .iter()
+        .filter(|l| result.match_column(l).is_some())
+        .collect();
+
+    writeln!(f, "--- a/{}", result.path.display())?;
+    writeln!(f, "+++ b/{}", result.path.display())?;
+    // How many lines the hunks so far have added, to number the new side.
+    let mut delta: isize = 0;
+    let mut rest = matched.as_slice();
+    while let Some(first) = rest.first() {
+        let len = rest
+            .windows(2)
+            .position(|w| w[0].number + 1 != w[1].number)
+            .map_or(rest.len(), |i| i + 1);
+        let (hunk, tail) = rest.split_at(len);
+        rest = tail;
+
+        // Replaced all at once, since a match can run across several lines of the hunk.
+        let old = grep_result::join(hunk.iter().copied());
+        let new = result.replaced(first.offset, &old);
+        let mut new_lines: Vec<&[u8]> = new.split(|b| *b == b'\n').collect();
+        // All but the last line still end in the `\r` of a `\r\n` terminator.
+        let last = new_lines.len() - 1;
+        for line in &mut new_lines[..last] {
+            *line = line.strip_suffix(b"\r").unwrap_or(line);
+        }
+
+        let old_start = first.number + 1;
+        let new_start = old_start as isize + delta;
+        writeln!(
+            f,
+            "@@ -{},{} +{},{} @@",
+            old_start,
+            hunk.len(),
+            new_start,
+            new_lines.len()
+        )?;
+        for line in hunk {
+            writeln!(f, "-{}", String::from_utf8_lossy(&line.text))?;
+        }
+        for line in &new_lines {
+            writeln!(f, "+{}", String::from_utf8_lossy(line))?;
+        }
+        delta += new_lines.len() as isize - hunk.len() as isize;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{self, MatchOptions};
+    use crate::search::{self, SearchOptions};
+
+    /// Search `content` for `pattern`, replacing matches with `replace` if set, and print
+    /// the result with `options`.
+    fn print(
+        pattern: &str,
+        replace: Option<&str>,
+        content: &[u8],
+        options: OutputOptions,
+    ) -> String {
+        let match_options = MatchOptions {
+            multiline: true,
+            ..MatchOptions::default()
+        };
+        let matcher = matcher::build(&[pattern.to_string()], &match_options).unwrap();
+        let search_options = SearchOptions {
+            before_context: options.before_context,
+            after_context: options.after_context,
+            replace: replace.map(|r| r.as_bytes().to_vec()),
+            multiline: true,
+            ..SearchOptions::default()
+        };
+        let found = search::search_slice(matcher.as_ref(), content, &search_options).unwrap();
+        let result = found.into_result("f".into(), 0);
+        result.display(&options).to_string()
+    }
+
+    const CRLF: &[u8] = b"xa1\r\nxa2\r\nxa3\r\n";
+
+    #[test]
+    fn crlf_lines_are_one_block() {
+        let options = OutputOptions {
+            line_number: true,
+            ..OutputOptions::default()
+        };
+        let printed = print("xa", None, CRLF, options);
+        assert_eq!(printed, ">>> (#0) \"f\"\n1:xa1\n2:xa2\n3:xa3\n");
+    }
+
+    #[test]
+    fn crlf_lines_apart_are_separate_blocks() {
+        let options = OutputOptions {
+            line_number: true,
+            ..OutputOptions::default()
+        };
+        let printed = print("a", None, b"a\r\nb\r\n\r\nx\r\na\r\n", options);
+        assert_eq!(printed, ">>> (#0) \"f\"\n1:a\n--\n5:a\n");
+    }
+
+    #[test]
+    fn crlf_diff_replaces_in_place() {
+        let options = OutputOptions {
+            diff: true,
+            ..OutputOptions::default()
+        };
+        let printed = print("a", Some("Z"), CRLF, options);
+        assert_eq!(
+            printed,
+            "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n-xa1\n-xa2\n-xa3\n+xZ1\n+xZ2\n+xZ3\n"
+        );
+    }
+
+    #[test]
+    fn crlf_multiline_replacement_shows_what_write_leaves() {
+        let line_numbers = OutputOptions {
+            line_number: true,
+            ..OutputOptions::default()
+        };
+        let printed = print(r"a1\r\nxa", Some("Q"), CRLF, line_numbers);
+        assert_eq!(printed, ">>> (#0) \"f\"\n1:xQ2\n");
+
+        let diff = OutputOptions {
+            diff: true,
+            ..OutputOptions::default()
+        };
+        let printed = print(r"a1\r\nxa", Some("Q"), CRLF, diff);
+        assert_eq!(
+            printed,
+            "--- a/f\n+++ b/f\n@@ -1,2 +1,1 @@\n-xa1\n-xa2\n+xQ2\n"
+        );
+
+        let underlined = print(r"a1\r\nxa", Some("Q"), CRLF, OutputOptions::default());
+        assert_eq!(underlined, ">>> (#0) \"f\"\nxQ2\n ^ \n");
+    }
+
+    #[test]
+    fn crlf_multiline_match_is_underlined_per_line() {
+        let printed = print(r"a1\r\nxa", None, CRLF, OutputOptions::default());
+        assert_eq!(printed, ">>> (#0) \"f\"\nxa1\n ^^\nxa2\n^^ \n");
+    }
+}
@@ -0,0 +1,83 @@
+use pcre2::bytes::{CaptureLocations, Regex, RegexBuilder};
+use std::ops::Range;
+
+use crate::matcher::Matcher;
+
+/// Compile `pattern` with PCRE2, JIT-compiled when the platform supports it.
+/// `^` and `$` match at the start and end of every line.
+pub fn build(pattern: &str, ignore_case: bool) -> Result<Regex, pcre2::Error> {
+    RegexBuilder::new()
+        .caseless(ignore_case)
+        .multi_line(true)
+        .crlf(true)
+        .jit_if_available(true)
+        .build(pattern)
+}
+
+impl Matcher for Regex {
+    fn find_at(&self, haystack: &[u8], start: usize) -> Option<Range<usize>> {
+        // Errors here are resource limits being hit, such as backtracking too much.
+        // Like a failed match, they end the search of this haystack.
+        Regex::find_at(self, haystack, start)
+            .ok()
+            .flatten()
+            .map(|m| m.start()..m.end())
+    }
+
+    fn expand_at(&self, haystack: &[u8], start: usize, template: &[u8], dst: &mut Vec<u8>) {
+        let mut locs = self.capture_locations();
+        if let Ok(Some(_)) = self.captures_read_at(&mut locs, haystack, start) {
+            expand(self, &locs, haystack, template, dst);
+        }
+    }
+}
+
+/// The `regex` crate's template syntax: `$1`, `${1}`, `$name`, `${name}` and `$$`.
+/// Groups that don't exist or didn't participate expand to nothing.
+fn expand(
+    regex: &Regex,
+    locs: &CaptureLocations,
+    haystack: &[u8],
+    mut template: &[u8],
+    dst: &mut Vec<u8>,
+) {
+    let is_name = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+    while let Some(i) = template.iter().position(|&b| b == b'$') {
+        dst.extend_from_slice(&template[..i]);
+        template = &template[i + 1..];
+        if let Some(rest) = template.strip_prefix(b"$") {
+            dst.push(b'$');
+            template = rest;
+            continue;
+        }
+        let (name, rest) = match template.strip_prefix(b"{") {
+            Some(braced) => match braced.iter().position(|&b| b == b'}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => (&b""[..], template),
+            },
+            None => {
+                let end = template
+                    .iter()
+                    .position(|b| !is_name(b))
+                    .unwrap_or(template.len());
+                template.split_at(end)
+            }
+        };
+        if name.is_empty() {
+            dst.push(b'$');
+            continue;
+        }
+        template = rest;
+        let name = String::from_utf8_lossy(name);
+        let group = name.parse::<usize>().ok().or_else(|| {
+            regex
+                .capture_names()
+                .iter()
+                .position(|n| n.as_deref() == Some(&*name))
+        });
+        if let Some((s, e)) = group.and_then(|g| locs.get(g)) {
+            dst.extend_from_slice(&haystack[s..e]);
+        }
+    }
+    dst.extend_from_slice(template);
+}
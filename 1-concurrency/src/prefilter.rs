@@ -0,0 +1,148 @@
+use aho_corasick::AhoCorasick;
+use memchr::memmem;
+use regex_syntax::hir::literal::{Extractor, Seq};
+use regex_syntax::hir::{Hir, HirKind, Look};
+use regex_syntax::ParserBuilder;
+use std::cmp::Reverse;
+
+/// Literals shorter than this occur too often to be worth looking for first.
+const MIN_LITERAL_LEN: usize = 2;
+
+/// With more literals than this, as case insensitive ones quickly get, looking for them
+/// is slower than running the regex.
+const MAX_LITERALS: usize = 16;
+
+/// Finds the places where a match can be, from literals that every match contains.
+/// A single literal is found with memchr's `memmem`, several with aho-corasick, which
+/// uses the SIMD Teddy searcher for small sets.
+pub struct Prefilter {
+    finder: Finder,
+}
+
+enum Finder {
+    One(Box<memmem::Finder<'static>>),
+    Many(AhoCorasick),
+}
+
+impl Prefilter {
+    /// The prefilter for `pattern`, in `regex` syntax, or `None` if no literal has to occur
+    /// in every match, the literals are too short to pay off or the regex engine finds
+    /// them just as well on its own.
+    pub fn new(pattern: &str, ignore_case: bool) -> Option<Self> {
+        // The matcher only sees the lines with a literal on them, on which `\z` matches
+        // at the end of every line rather than at the end of the text.
+        let hir = parse(pattern, ignore_case)?;
+        if hir.properties().look_set().contains(Look::End) {
+            return None;
+        }
+
+        // The regex engine already skips ahead to the literals a match starts with. Looking
+        // for them first would only add work for every line they occur on.
+        let starts = Extractor::new().extract(&hir);
+        if starts
+            .min_literal_len()
+            .is_some_and(|len| len >= MIN_LITERAL_LEN)
+        {
+            return None;
+        }
+
+        // Case insensitive literals are looked for ASCII case insensitively, instead of as
+        // every combination of cases. That misses the Kelvin sign and the long s, which
+        // also match `k` and `s`.
+        let seq = inner(&parse(pattern, false)?)?;
+        let literals: Vec<&[u8]> = seq.literals()?.iter().map(|l| l.as_bytes()).collect();
+        let ascii = |b: &u8| b.is_ascii() && !matches!(b.to_ascii_lowercase(), b'k' | b's');
+        if ignore_case && !literals.iter().all(|l| l.iter().all(ascii)) {
+            return None;
+        }
+        let finder = match literals.as_slice() {
+            [literal] if !ignore_case => {
+                Finder::One(Box::new(memmem::Finder::new(literal).into_owned()))
+            }
+            _ => Finder::Many(
+                AhoCorasick::builder()
+                    .ascii_case_insensitive(ignore_case)
+                    .build(&literals)
+                    .ok()?,
+            ),
+        };
+        Some(Self { finder })
+    }
+
+    /// The offset of the first of the literals that starts at or after `start`.
+    pub fn find(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let rest = &haystack[start..];
+        let found = match &self.finder {
+            Finder::One(finder) => finder.find(rest),
+            Finder::Many(ac) => ac.find(rest).map(|m| m.start()),
+        };
+        found.map(|i| start + i)
+    }
+}
+
+/// The literals of which every match of `pattern` contains at least one, case sensitively
+/// as written in it, or `None` if there are no such literals.
+pub fn required_literals(pattern: &str) -> Option<Vec<Vec<u8>>> {
+    let seq = required(&parse(pattern, false)?)?;
+    Some(
+        seq.literals()?
+            .iter()
+            .map(|l| l.as_bytes().to_vec())
+            .collect(),
+    )
+}
+
+/// Parse `pattern` the way `matcher::build` compiles it when matches stay within a line.
+fn parse(pattern: &str, case_insensitive: bool) -> Option<Hir> {
+    ParserBuilder::new()
+        .utf8(false)
+        .multi_line(true)
+        .crlf(true)
+        .case_insensitive(case_insensitive)
+        .build()
+        .parse(pattern)
+        .ok()
+}
+
+/// The best set of literals of which every match of `hir` contains at least one: those a
+/// match has to start with, or those of one of the parts it is made of.
+fn required(hir: &Hir) -> Option<Seq> {
+    best(std::iter::once(prefixes(hir)).chain(inner(hir)))
+}
+
+/// Like `required`, but only from the parts of `hir`, not from what its matches start with.
+fn inner(hir: &Hir) -> Option<Seq> {
+    match hir.kind() {
+        // What the rest of the concatenation starts with, from every part on, so literals
+        // that were split into one part per character, like case insensitive ones, are
+        // found whole.
+        HirKind::Concat(parts) => best(
+            (1..parts.len())
+                .map(|i| prefixes(&Hir::concat(parts[i..].to_vec())))
+                .chain(parts.iter().filter_map(inner)),
+        ),
+        HirKind::Capture(capture) => required(&capture.sub),
+        HirKind::Repetition(rep) if rep.min > 0 => required(&rep.sub),
+        _ => None,
+    }
+}
+
+/// The literals every match of `hir` starts with.
+fn prefixes(hir: &Hir) -> Seq {
+    let mut seq = Extractor::new().extract(hir);
+    seq.optimize_for_prefix_by_preference();
+    seq
+}
+
+fn is_usable(seq: &Seq) -> bool {
+    seq.min_literal_len()
+        .is_some_and(|len| len >= MIN_LITERAL_LEN)
+        && seq.len().is_some_and(|len| len <= MAX_LITERALS)
+}
+
+/// The first of `seqs` with the longest shortest literal, since longer literals are
+/// rarer, and with the fewest literals among those, which are faster to look for.
+fn best(seqs: impl Iterator<Item = Seq>) -> Option<Seq> {
+    seqs.filter(is_usable)
+        .min_by_key(|seq| (Reverse(seq.min_literal_len()), seq.len()))
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+
+use crate::grep_result::GrepResult;
+
+/// Puts results that arrive out of order back in `search_ctr` order.
+/// In unordered mode results are handed out as soon as they arrive and are
+/// renumbered, so that the printed `search_ctr` still always increases.
+pub struct ReorderBuffer {
+    unordered: bool,
+    next_id: usize,
+    received: usize,
+    buffer: BTreeMap<usize, GrepResult>,
+}
+
+impl ReorderBuffer {
+    pub fn new(unordered: bool) -> Self {
+        Self {
+            unordered,
+            next_id: 0,
+            received: 0,
+            buffer: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, mut result: GrepResult) {
+        if self.unordered {
+            result.search_ctr = self.received;
+        }
+        self.received += 1;
+        self.buffer.insert(result.search_ctr, result);
+    }
+
+    /// The next result to print, if it has arrived.
+    pub fn pop(&mut self) -> Option<GrepResult> {
+        let result = self.buffer.remove(&self.next_id)?;
+        self.next_id += 1;
+        Some(result)
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::matcher::Matcher;
+
+/// Expand `template` for every match in `ranges`. `$1`, `${name}` and `$$` work the same
+/// way as in `Regex::replace`. The matches are re-run from their start offset to get at
+/// their capture groups, so `ranges` must be matches of `matcher` in `content`.
+pub fn expand(
+    matcher: &dyn Matcher,
+    content: &[u8],
+    ranges: &[Range<usize>],
+    template: &[u8],
+) -> Vec<Vec<u8>> {
+    ranges
+        .iter()
+        .map(|range| {
+            let mut replacement = Vec::new();
+            matcher.expand_at(content, range.start, template, &mut replacement);
+            replacement
+        })
+        .collect()
+}
+
+/// Splice `replacements` into `text`, which starts at file offset `base`. Ranges that only
+/// partly overlap `text` have their overlapping part removed, and their replacement is only
+/// inserted in the piece of text where the range starts.
+pub fn apply(
+    text: &[u8],
+    base: usize,
+    ranges: &[Range<usize>],
+    replacements: &[Vec<u8>],
+) -> Vec<u8> {
+    let end = base + text.len();
+    let mut out = Vec::with_capacity(text.len());
+    let mut cursor = 0;
+    for (range, replacement) in ranges.iter().zip(replacements) {
+        if range.end < base || range.start > end || (range.end == base && range.start < base) {
+            continue;
+        }
+        let start = range.start.max(base) - base;
+        out.extend_from_slice(&text[cursor..start]);
+        if range.start >= base {
+            out.extend_from_slice(replacement);
+        }
+        cursor = range.end.min(end) - base;
+    }
+    out.extend_from_slice(&text[cursor..]);
+    out
+}
+
+/// Atomically replace the contents of the file at `path` with `content`, as built by
+/// `apply`. The new contents go to a temporary file next to the original first,
+/// which is then renamed over it, so readers never see a half-written file.
+/// A symbolic link is followed, so the file it points to is rewritten, not the link.
+pub fn rewrite(path: &Path, content: &[u8]) -> io::Result<()> {
+    let path = &fs::canonicalize(path)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.mygrep-{}.tmp", name, std::process::id()));
+
+    let result = (|| {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::set_permissions(&tmp, fs::metadata(path)?.permissions())?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{self, MatchOptions};
+
+    #[test]
+    fn expand_fills_in_capture_groups() {
+        let matcher = matcher::build(
+            &[r"(\w+)@(?P<host>\w+)".to_string()],
+            &MatchOptions::default(),
+        )
+        .unwrap();
+        let content = b"a@b and cc@dd";
+        let ranges: Vec<_> = matcher.find_iter(content).collect();
+        let replacements = expand(matcher.as_ref(), content, &ranges, b"${host}:$1$$");
+        assert_eq!(replacements, [b"b:a$".to_vec(), b"dd:cc$".to_vec()]);
+    }
+
+    #[test]
+    fn apply_splices_every_replacement() {
+        let replacements = [b"X".to_vec(), b"".to_vec(), b"YY".to_vec()];
+        let out = apply(b"abcdefgh", 0, &[0..1, 3..5, 8..8], &replacements);
+        assert_eq!(out, b"XbcfghYY");
+    }
+
+    #[test]
+    fn apply_to_text_from_the_middle_of_a_file() {
+        // "line1\nline2\n" with a match over "1\nli", applied to each line on its own.
+        let ranges = [Range { start: 4, end: 8 }];
+        let replacements = [b"Q".to_vec()];
+        assert_eq!(apply(b"line1", 0, &ranges, &replacements), b"lineQ");
+        assert_eq!(apply(b"line2", 6, &ranges, &replacements), b"ne2");
+        assert_eq!(
+            apply(b"line1\nline2", 0, &ranges, &replacements),
+            b"lineQne2"
+        );
+        // A match that ends right where the text starts leaves it alone.
+        assert_eq!(apply(b"xyz", 8, &ranges, &replacements), b"xyz");
+    }
+
+    #[test]
+    fn rewrite_replaces_the_contents_and_keeps_the_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f.txt");
+        fs::write(&path, "old\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        rewrite(&path, b"new\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        // The temporary file was renamed away.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewrite_follows_symbolic_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target.txt");
+        let link = dir.path().join("link.txt");
+        fs::write(&target, "old\n").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        rewrite(&link, b"new\n").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"new\n");
+    }
+
+    #[test]
+    fn rewrite_of_a_missing_file_fails_and_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(rewrite(&dir.path().join("missing.txt"), b"new\n").is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,775 @@
+use memchr::{memchr, memchr_iter, memrchr};
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::archive;
+use crate::binary::{self, BinaryMode};
+use crate::cancel::CancelToken;
+use crate::decompress;
+use crate::encoding::{self, Encoding};
+use crate::grep_result::{GrepResult, Line};
+use crate::matcher::Matcher;
+use crate::replace;
+
+/// Regular files at least this large are memory-mapped instead of read into a `Vec`.
+pub const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// The path that stands for standard input.
+pub const STDIN: &str = "-";
+
+/// How much is read at once when streaming from pipes and other special files.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Options that influence what a worker searches for and what it keeps of a file.
+#[derive(Debug, Default, Clone)]
+pub struct SearchOptions {
+    /// What to do with files that look binary
+    pub binary: BinaryMode,
+
+    /// Number of lines to keep before every matching line
+    pub before_context: usize,
+
+    /// Number of lines to keep after every matching line
+    pub after_context: usize,
+
+    /// Search inside compressed files instead of their raw bytes
+    pub decompress: bool,
+
+    /// Select the lines that do *not* match
+    pub invert: bool,
+
+    /// Report the files that have no match instead of the ones that do
+    pub files_without_match: bool,
+
+    /// Replace every match with this template, which may refer to capture groups
+    pub replace: Option<Vec<u8>>,
+
+    /// Write the replacements back to the files that matched
+    pub write: bool,
+
+    /// Matches may span lines, so streams are searched in one piece instead of chunk by chunk
+    pub multiline: bool,
+
+    /// Stop searching a file after this many matching lines
+    pub max_count: Option<usize>,
+
+    /// Give up on the file being searched once this is cancelled
+    pub cancel: CancelToken,
+
+    /// Which files to transcode to UTF-8 before searching them
+    pub encoding: Encoding,
+
+    /// Search the files inside .zip, .jar, .tar and .tar.gz archives, each on its own
+    pub search_zip: bool,
+
+    /// Record how far every regular file was searched here. Set by `Searcher::watch`
+    pub searched: Option<Arc<Searched>>,
+}
+
+/// How far every regular file was searched, so that watching can go on from there.
+#[derive(Debug, Default)]
+pub struct Searched(Mutex<HashMap<PathBuf, SearchedTo>>);
+
+/// The end of what was searched of a file.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SearchedTo {
+    /// The size of the file when it was searched
+    pub len: u64,
+
+    /// The offset and 0-based number of its last line
+    pub last_line: (u64, usize),
+}
+
+impl Searched {
+    /// Note that the file at `path` was searched, with `content` as its contents.
+    fn record(&self, path: &Path, content: &[u8]) {
+        let last = line_start(content, content.len());
+        let to = SearchedTo {
+            len: content.len() as u64,
+            last_line: (last as u64, count_lines(&content[..last])),
+        };
+        let mut files = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        files.insert(path.to_path_buf(), to);
+    }
+
+    /// Everything recorded so far.
+    pub(crate) fn take(&self) -> HashMap<PathBuf, SearchedTo> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl SearchOptions {
+    /// Turn the outcome of searching a file into what should be reported: its matches or,
+    /// with `files_without_match`, an empty result when the file didn't match. A file
+    /// that was skipped is never reported.
+    fn report(&self, outcome: Outcome) -> Option<Found> {
+        let found = match outcome {
+            Outcome::Searched(found) => found,
+            Outcome::Skipped => return None,
+        };
+        if !self.files_without_match {
+            return found;
+        }
+        match found {
+            Some(_) => None,
+            None => Some(Found {
+                lines: Vec::new(),
+                ranges: Vec::new(),
+                replacements: Vec::new(),
+                binary: false,
+                encoding: None,
+            }),
+        }
+    }
+}
+
+/// How searching a file went, before `SearchOptions::report` decides what to report.
+enum Outcome {
+    /// The file was searched, and this is what matched in it, if anything
+    Searched(Option<Found>),
+    /// The file wasn't searched to the end, because it is binary and binary files are
+    /// skipped, or because the search was cancelled. It is neither a match nor a non-match.
+    Skipped,
+}
+
+/// The matches in one file, before it has been assigned a `search_ctr`.
+pub struct Found {
+    pub lines: Vec<Line>,
+    pub ranges: Vec<Range<usize>>,
+    pub replacements: Vec<Vec<u8>>,
+    pub binary: bool,
+    pub encoding: Option<Encoding>,
+}
+
+impl Found {
+    pub fn into_result(self, path: PathBuf, search_ctr: usize) -> GrepResult {
+        GrepResult {
+            path: if is_stdin(&path) {
+                PathBuf::from("<stdin>")
+            } else {
+                path
+            },
+            lines: self.lines,
+            ranges: self.ranges,
+            replacements: self.replacements,
+            search_ctr,
+            binary: self.binary,
+            encoding: self.encoding,
+        }
+    }
+
+    /// Mark these matches as found in text transcoded from `encoding`.
+    fn transcoded(mut self, encoding: Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// Move these matches from the start of the file to byte `offset`, which is at the
+    /// start of line `line` (0-based), for matches found in a slice from the middle of a file.
+    pub fn shift(&mut self, offset: usize, line: usize) {
+        for l in &mut self.lines {
+            l.offset += offset;
+            l.number += line;
+        }
+        for r in &mut self.ranges {
+            *r = r.start + offset..r.end + offset;
+        }
+    }
+}
+
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == STDIN
+}
+
+/// Whether the file at `path` is a plain regular file small enough to be read into memory
+/// in one go. Everything else should go through `search_path`.
+pub fn is_small_file(path: &Path, metadata: &Metadata, options: &SearchOptions) -> bool {
+    metadata.is_file()
+        && metadata.len() < MMAP_THRESHOLD
+        && !(options.decompress && decompress::Format::of(path).is_some())
+        && !(options.search_zip && archive::Format::of(path).is_some())
+        && !options.write
+}
+
+/// Search the file at `path` like `search_path` and hand the outcome to `f`. With
+/// `search_zip`, an archive is searched entry by entry instead, in the order the entries
+/// are stored in, and `f` is called once for every file inside it, as `archive.tar!/path`.
+/// Archive entries are never rewritten.
+pub fn search_each(
+    matcher: &dyn Matcher,
+    path: PathBuf,
+    options: &SearchOptions,
+    mut f: impl FnMut(PathBuf, io::Result<Option<Found>>),
+) {
+    let format = match archive::Format::of(&path) {
+        Some(format) if options.search_zip && !is_stdin(&path) => format,
+        _ => {
+            let found = search_path(matcher, &path, options);
+            return f(path, found);
+        }
+    };
+    let entries = format.for_each_entry(&path, |entry, content| {
+        f(
+            entry,
+            content.map(|content| search_content(matcher, &content, options)),
+        );
+        !options.cancel.is_cancelled()
+    });
+    if let Err(e) = entries {
+        f(path, Err(e));
+    }
+}
+
+/// Search the file at `path`, picking the cheapest way to get at its bytes:
+/// small files are read into memory, large ones are memory-mapped and
+/// anything that isn't a regular file (pipes, devices, stdin) or has to be
+/// decompressed is streamed. Files in another encoding than UTF-8 are transcoded
+/// first. With `write`, the replacements are written back to regular files,
+/// in the encoding they were in.
+pub fn search_path(
+    matcher: &dyn Matcher,
+    path: &Path,
+    options: &SearchOptions,
+) -> io::Result<Option<Found>> {
+    if is_stdin(path) {
+        return search_stream(matcher, io::stdin().lock(), options);
+    }
+
+    let mut file = File::open(path)?;
+    if options.decompress {
+        if let Some(format) = decompress::Format::of(path) {
+            return search_stream(matcher, format.decoder(file)?, options);
+        }
+    }
+
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return search_stream(matcher, file, options);
+    }
+
+    if metadata.len() >= MMAP_THRESHOLD {
+        // SAFETY: the map is only read while we hold it. If another process truncates
+        // the file underneath us we may fault, the same trade-off every mmap-based grep makes.
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            return search_and_rewrite(matcher, path, &map, options);
+        }
+    }
+
+    let mut content = Vec::with_capacity(metadata.len() as usize);
+    file.read_to_end(&mut content)?;
+    search_and_rewrite(matcher, path, &content, options)
+}
+
+fn search_and_rewrite(
+    matcher: &dyn Matcher,
+    path: &Path,
+    content: &[u8],
+    options: &SearchOptions,
+) -> io::Result<Option<Found>> {
+    if let Some(searched) = &options.searched {
+        searched.record(path, content);
+    }
+    let encoding = encoding_of(content, options);
+    let text = match encoding {
+        Some(encoding) => &encoding.decode(content)[..],
+        None => content,
+    };
+    let found = search_slice(matcher, text, options);
+    if let Some(found) = found.as_ref().filter(|f| options.write && !f.binary) {
+        if !found.ranges.is_empty() {
+            let new = replace::apply(text, 0, &found.ranges, &found.replacements);
+            let new = match encoding {
+                Some(encoding) => encoding.encode(&new, encoding.has_bom(content))?,
+                None => new,
+            };
+            replace::rewrite(path, &new)?;
+        }
+    }
+    Ok(match encoding {
+        Some(encoding) => found.map(|f| f.transcoded(encoding)),
+        None => found,
+    })
+}
+
+/// Search a stream chunk by chunk or, with `multiline` or when it has to be transcoded,
+/// read it whole first, so that matches can cross what would have been chunk boundaries.
+fn search_stream(
+    matcher: &dyn Matcher,
+    mut reader: impl Read,
+    options: &SearchOptions,
+) -> io::Result<Option<Found>> {
+    let mut head = Vec::with_capacity(encoding::SNIFF_LEN);
+    (&mut reader)
+        .take(encoding::SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    let encoding = options.encoding.of(&head);
+    let mut reader = head.as_slice().chain(reader);
+    if encoding.is_none() && !options.multiline {
+        return search_reader(matcher, reader, options);
+    }
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content)?;
+    Ok(search_content(matcher, &content, options))
+}
+
+/// Search the contents of the regular file at `path`, already read into memory, like
+/// `search_path` does. For files that `is_small_file` accepts.
+pub fn search_read(
+    matcher: &dyn Matcher,
+    path: &Path,
+    content: &[u8],
+    options: &SearchOptions,
+) -> Option<Found> {
+    if let Some(searched) = &options.searched {
+        searched.record(path, content);
+    }
+    search_content(matcher, content, options)
+}
+
+/// Search the entire contents of a file, transcoded to UTF-8 first if they have to be.
+pub fn search_content(
+    matcher: &dyn Matcher,
+    content: &[u8],
+    options: &SearchOptions,
+) -> Option<Found> {
+    match encoding_of(content, options) {
+        Some(encoding) => search_slice(matcher, &encoding.decode(content), options)
+            .map(|f| f.transcoded(encoding)),
+        None => search_slice(matcher, content, options),
+    }
+}
+
+/// The encoding the file that holds `content` has to be transcoded from, if any.
+fn encoding_of(content: &[u8], options: &SearchOptions) -> Option<Encoding> {
+    options
+        .encoding
+        .of(&content[..content.len().min(encoding::SNIFF_LEN)])
+}
+
+/// Search a buffer that holds an entire file, or the part of it after some line, as is.
+pub fn search_slice(
+    matcher: &dyn Matcher,
+    content: &[u8],
+    options: &SearchOptions,
+) -> Option<Found> {
+    options.report(find_in_slice(matcher, content, options))
+}
+
+fn find_in_slice(matcher: &dyn Matcher, content: &[u8], options: &SearchOptions) -> Outcome {
+    let is_binary = binary::is_binary(content);
+    if is_binary && options.binary == BinaryMode::Skip {
+        return Outcome::Skipped;
+    }
+
+    let mut limit = LineLimit::new(options.max_count);
+    let ranges: Vec<Range<usize>> = find_ranges(matcher, content, options)
+        .take_while(|r| limit.admit(content, r))
+        .take_while(|_| !options.cancel.is_cancelled())
+        .collect();
+    if options.cancel.is_cancelled() {
+        return Outcome::Skipped;
+    }
+    if ranges.is_empty() {
+        return Outcome::Searched(None);
+    }
+
+    let report = is_binary && options.binary == BinaryMode::Report;
+    let mut lines = LineCollector::new(options);
+    let mut replacements = Vec::new();
+    if !report {
+        lines.collect(content, 0, 0, &ranges);
+        replacements = expand(matcher, content, &ranges, options);
+    }
+    Outcome::Searched(Some(Found {
+        lines: lines.lines,
+        ranges,
+        replacements,
+        binary: report,
+        encoding: None,
+    }))
+}
+
+/// Search a stream chunk by chunk, so that pipes and special files never have to fit in memory.
+/// Only complete lines are searched. The tail of every chunk overlaps with the next one, so
+/// that partial lines and the lines needed for `before_context` are still available.
+/// Patterns that span lines can't match across a chunk boundary.
+pub fn search_reader(
+    matcher: &dyn Matcher,
+    reader: impl Read,
+    options: &SearchOptions,
+) -> io::Result<Option<Found>> {
+    Ok(options.report(find_in_reader(matcher, reader, options)?))
+}
+
+fn find_in_reader(
+    matcher: &dyn Matcher,
+    mut reader: impl Read,
+    options: &SearchOptions,
+) -> io::Result<Outcome> {
+    let mut buf: Vec<u8> = Vec::with_capacity(CHUNK_SIZE);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut lines = LineCollector::new(options);
+    let mut ranges = Vec::new();
+    let mut replacements = Vec::new();
+    let mut is_binary = None;
+    let mut limit = LineLimit::new(options.max_count);
+
+    // File offset and line number of `buf[0]`, and how far the file has been searched.
+    let mut base_offset = 0;
+    let mut base_line = 0;
+    let mut searched = 0;
+
+    loop {
+        if options.cancel.is_cancelled() {
+            return Ok(Outcome::Skipped);
+        }
+        let n = match reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        let eof = n == 0;
+
+        let binary = *is_binary.get_or_insert_with(|| binary::is_binary(&buf));
+        if binary && options.binary == BinaryMode::Skip {
+            return Ok(Outcome::Skipped);
+        }
+
+        let region_end = match (eof, memrchr(b'\n', &buf)) {
+            (true, _) => buf.len(),
+            (false, Some(i)) if base_offset + i + 1 > searched => i + 1,
+            // No complete unsearched line yet, keep reading.
+            (false, _) => continue,
+        };
+        let region = &buf[..region_end];
+
+        limit.line_end = 0;
+        let local: Vec<Range<usize>> = find_ranges(matcher, region, options)
+            .filter(|m| base_offset + m.start >= searched)
+            .filter(|m| eof || m.start < region_end)
+            .take_while(|m| limit.admit(region, m))
+            .collect();
+        let found: Vec<Range<usize>> = local
+            .iter()
+            .map(|m| base_offset + m.start..base_offset + m.end)
+            .collect();
+        // In report mode the first match is all we need to know.
+        let report = binary && options.binary == BinaryMode::Report;
+        if !report {
+            lines.collect(region, base_offset, base_line, &found);
+            replacements.extend(expand(matcher, region, &local, options));
+        }
+        // Past `max_count`, only the after-context of the last match is still needed.
+        let done =
+            eof || (report && !found.is_empty()) || (limit.reached() && lines.pending.is_none());
+        ranges.extend(found);
+        searched = base_offset + region_end;
+
+        if done {
+            break;
+        }
+
+        // Keep the last `before_context` searched lines and the unsearched tail.
+        let keep_from = nth_line_start_before(region, region_end, options.before_context);
+        base_line += count_lines(&buf[..keep_from]);
+        base_offset += keep_from;
+        buf.drain(..keep_from);
+    }
+
+    if ranges.is_empty() {
+        return Ok(Outcome::Searched(None));
+    }
+    let report = is_binary == Some(true) && options.binary == BinaryMode::Report;
+    Ok(Outcome::Searched(Some(Found {
+        lines: if report { Vec::new() } else { lines.lines },
+        ranges,
+        replacements,
+        binary: report,
+        encoding: None,
+    })))
+}
+
+/// The replacement for every range when `options.replace` is set, nothing otherwise.
+fn expand(
+    matcher: &dyn Matcher,
+    content: &[u8],
+    ranges: &[Range<usize>],
+    options: &SearchOptions,
+) -> Vec<Vec<u8>> {
+    match &options.replace {
+        Some(template) => replace::expand(matcher, content, ranges, template),
+        None => Vec::new(),
+    }
+}
+
+/// The ranges in `content` that are selected: the matcher matches or, when inverting,
+/// every line that contains no match at all. They are found lazily, so that `max_count`
+/// can stop the search early.
+fn find_ranges<'a>(
+    matcher: &'a dyn Matcher,
+    content: &'a [u8],
+    options: &SearchOptions,
+) -> Box<dyn Iterator<Item = Range<usize>> + 'a> {
+    let matches = matcher.find_iter(content);
+    if !options.invert {
+        return Box::new(matches);
+    }
+
+    let mut matches = matches.peekable();
+    let mut start = 0;
+    Box::new(std::iter::from_fn(move || {
+        while start < content.len() {
+            let line = start;
+            let end = memchr(b'\n', &content[line..]).map_or(content.len(), |i| line + i);
+            start = end + 1;
+            // Skip the matches that end before this line, then see if one touches it.
+            let before_line = |m: &Range<usize>| m.start < line && m.end <= line;
+            while matches.next_if(before_line).is_some() {}
+            if matches.peek().is_none_or(|m| m.start > end) {
+                let text = &content[line..end];
+                let len = text.strip_suffix(b"\r").unwrap_or(text).len();
+                return Some(line..line + len);
+            }
+        }
+        None
+    }))
+}
+
+/// Counts the lines that selected ranges start on, to stop after `max_count` of them.
+struct LineLimit {
+    max: Option<usize>,
+    lines: usize,
+    /// End of the last counted line in the current region, so further matches on it are free.
+    line_end: usize,
+}
+
+impl LineLimit {
+    fn new(max: Option<usize>) -> Self {
+        Self {
+            max,
+            lines: 0,
+            line_end: 0,
+        }
+    }
+
+    /// Whether `range` in `content` may still be selected.
+    fn admit(&mut self, content: &[u8], range: &Range<usize>) -> bool {
+        let Some(max) = self.max else {
+            return true;
+        };
+        if range.start < self.line_end {
+            return true;
+        }
+        if self.lines >= max {
+            return false;
+        }
+        self.lines += 1;
+        self.line_end =
+            memchr(b'\n', &content[range.start..]).map_or(content.len(), |i| range.start + i) + 1;
+        true
+    }
+
+    fn reached(&self) -> bool {
+        self.max.is_some_and(|max| self.lines >= max)
+    }
+}
+
+/// Builds the list of matched and context lines for a file, one region at a time.
+struct LineCollector {
+    before: usize,
+    after: usize,
+    lines: Vec<Line>,
+    /// After-context that ran past the end of the previous region, as the last line number to keep.
+    pending: Option<usize>,
+}
+
+impl LineCollector {
+    fn new(options: &SearchOptions) -> Self {
+        Self {
+            before: options.before_context,
+            after: options.after_context,
+            lines: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Keep the lines of `region` that are needed to print `ranges`. The region starts at
+    /// file offset `base_offset`, on line `base_line`, and must start at the start of a line.
+    fn collect(
+        &mut self,
+        region: &[u8],
+        base_offset: usize,
+        base_line: usize,
+        ranges: &[Range<usize>],
+    ) {
+        if let Some(until) = self.pending.take() {
+            self.push_lines(region, base_offset, 0, base_line, until);
+        }
+
+        // A line start in the region whose line number is known, so we only count forward.
+        let mut cursor = (0, base_line);
+        for range in ranges {
+            let start = range.start - base_offset;
+            let last_byte = (range.end - base_offset).max(start + 1) - 1;
+
+            let first = line_start(region, start);
+            let first_no = cursor.1 + count_lines(&region[cursor.0..first]);
+            let last = line_start(
+                region,
+                last_byte.min(region.len().saturating_sub(1)).max(first),
+            );
+            let last_no = first_no + count_lines(&region[first..last]);
+            cursor = (first, first_no);
+
+            let from = nth_line_start_before(region, first, self.before);
+            let from_no = first_no - count_lines(&region[from..first]);
+            self.push_lines(region, base_offset, from, from_no, last_no + self.after);
+        }
+    }
+
+    /// Push the lines of `region` starting at `start` (line `number`) up to and including line `until`.
+    fn push_lines(
+        &mut self,
+        region: &[u8],
+        base_offset: usize,
+        mut start: usize,
+        mut number: usize,
+        until: usize,
+    ) {
+        while number <= until {
+            if start >= region.len() && !(start == 0 && base_offset == 0) {
+                self.pending = Some(until);
+                return;
+            }
+            let end = memchr(b'\n', &region[start..]).map_or(region.len(), |i| start + i);
+            if self.lines.last().is_none_or(|l| l.number < number) {
+                let text = &region[start..end];
+                let text = text.strip_suffix(b"\r").unwrap_or(text);
+                self.lines.push(Line {
+                    number,
+                    offset: base_offset + start,
+                    text: text.to_vec(),
+                });
+            }
+            start = end + 1;
+            number += 1;
+        }
+    }
+}
+
+/// Offset of the start of the line containing byte `pos`.
+pub(crate) fn line_start(content: &[u8], pos: usize) -> usize {
+    memrchr(b'\n', &content[..pos]).map_or(0, |i| i + 1)
+}
+
+/// Offset of the start of the line `n` lines before the line starting at `start`.
+fn nth_line_start_before(content: &[u8], mut start: usize, n: usize) -> usize {
+    for _ in 0..n {
+        if start == 0 {
+            break;
+        }
+        start = line_start(content, start - 1);
+    }
+    start
+}
+
+pub(crate) fn count_lines(content: &[u8]) -> usize {
+    memchr_iter(b'\n', content).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{self, MatchOptions};
+    use std::fs;
+
+    fn matcher(pattern: &str) -> Box<dyn Matcher> {
+        matcher::build(&[pattern.to_string()], &MatchOptions::default()).unwrap()
+    }
+
+    fn rewrite_options(replace: &str, encoding: Encoding) -> SearchOptions {
+        SearchOptions {
+            replace: Some(replace.as_bytes().to_vec()),
+            write: true,
+            encoding,
+            ..SearchOptions::default()
+        }
+    }
+
+    #[test]
+    fn utf8_byte_order_mark_is_not_part_of_the_first_line() {
+        let found = search_content(
+            matcher("^hello").as_ref(),
+            b"\xEF\xBB\xBFhello\nhello\n",
+            &SearchOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(found.ranges, [0..5, 6..11]);
+        assert_eq!(found.encoding, Some(Encoding::Utf8));
+    }
+
+    #[test]
+    fn skipped_binary_files_are_not_listed_as_files_without_match() {
+        let options = SearchOptions {
+            files_without_match: true,
+            ..SearchOptions::default()
+        };
+        let hello = matcher("hello");
+        for content in [&b"hello\0world\n"[..], b"bye\0world\n"] {
+            assert!(search_slice(hello.as_ref(), content, &options).is_none());
+            let found = search_reader(hello.as_ref(), content, &options).unwrap();
+            assert!(found.is_none());
+        }
+        assert!(search_slice(hello.as_ref(), b"bye\n", &options).is_some());
+        assert!(search_slice(hello.as_ref(), b"hello\n", &options).is_none());
+
+        let options = SearchOptions {
+            binary: BinaryMode::Text,
+            ..options
+        };
+        assert!(search_slice(hello.as_ref(), b"bye\0world\n", &options).is_some());
+        assert!(search_slice(hello.as_ref(), b"hello\0world\n", &options).is_none());
+    }
+
+    #[test]
+    fn write_keeps_the_encoding_and_byte_order_mark() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f.txt");
+        for (encoding, before, after) in [
+            (
+                Encoding::Latin1,
+                &b"caf\xE9 x\n"[..],
+                &b"caf\xE9 \xE9\n"[..],
+            ),
+            (
+                Encoding::Auto,
+                b"\xEF\xBB\xBFx\n",
+                b"\xEF\xBB\xBF\xC3\xA9\n",
+            ),
+            (Encoding::Auto, b"\xFF\xFEx\0\n\0", b"\xFF\xFE\xE9\0\n\0"),
+        ] {
+            fs::write(&path, before).unwrap();
+            let options = rewrite_options("\u{e9}", encoding);
+            search_path(matcher("x").as_ref(), &path, &options).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), after, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn write_fails_and_leaves_the_file_alone_when_latin1_cannot_hold_the_replacement() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f.txt");
+        fs::write(&path, b"caf\xE9 x\n").unwrap();
+        let options = rewrite_options("\u{65e5}\u{672c}", Encoding::Latin1);
+        let found = search_path(matcher("x").as_ref(), &path, &options);
+        assert!(found.is_err_and(|e| e.kind() == io::ErrorKind::InvalidData));
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xE9 x\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,381 @@
+use clap::ValueEnum;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::backend::{self, Job};
+use crate::budget::Budget;
+use crate::cancel::CancelToken;
+use crate::diagnostics::Diagnostics;
+use crate::grep_result::GrepResult;
+use crate::index::Candidates;
+use crate::matcher::Matcher;
+use crate::search::{SearchOptions, Searched};
+use crate::walker::{WalkOptions, Walker};
+use crate::watch::{Watch, Watching};
+use crate::Error;
+
+/// How many results `Searcher::iter` lets pile up before the search waits for the consumer.
+const ITER_CHANNEL_CAPACITY: usize = 256;
+
+/// Kind selector for the bonus assignment
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    #[default]
+    SelfMade,
+    Rayon,
+    Tokio,
+}
+
+/// Receives the results of a search.
+pub trait Sink {
+    /// Called once for every reported file, in `search_ctr` order.
+    fn result(&mut self, result: GrepResult);
+
+    /// Called once after the last result.
+    fn finish(&mut self) {}
+}
+
+/// What a finished `Searcher::run` found.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    /// How many files were handed to the sink
+    pub results: usize,
+
+    /// How many errors were met along the way, such as files that couldn't be read
+    pub errors: usize,
+}
+
+/// A reusable search over a matcher, walker options and a backend.
+pub struct Searcher {
+    matcher: Arc<dyn Matcher>,
+    walk: WalkOptions,
+    search: Arc<SearchOptions>,
+    kind: Kind,
+    unordered: bool,
+    memory_budget: usize,
+    no_messages: bool,
+    sorted: bool,
+    cancel: CancelToken,
+    max_total: Option<usize>,
+    candidates: Option<Arc<Candidates>>,
+    threads: Option<usize>,
+}
+
+/// Builds a `Searcher`. Everything but the matcher has a sensible default.
+pub struct SearcherBuilder {
+    searcher: Searcher,
+}
+
+impl Searcher {
+    /// Start building a searcher for `matcher`, such as a `regex::bytes::Regex` or
+    /// whatever `matcher::build` returns.
+    pub fn builder(matcher: impl Matcher + 'static) -> SearcherBuilder {
+        SearcherBuilder {
+            searcher: Searcher {
+                matcher: Arc::new(matcher),
+                walk: WalkOptions::default(),
+                search: Arc::new(SearchOptions::default()),
+                kind: Kind::default(),
+                unordered: false,
+                memory_budget: 256 * 1024 * 1024,
+                no_messages: false,
+                sorted: false,
+                cancel: CancelToken::new(),
+                max_total: None,
+                candidates: None,
+                threads: None,
+            },
+        }
+    }
+
+    /// Search `roots` and hand every result to `sink`. Returns once the search is done.
+    /// Errors in single files don't stop the search, they are reported on stderr and counted.
+    pub fn run(&self, roots: &[PathBuf], sink: impl Sink + Send + 'static) -> Result<Stats, Error> {
+        self.run_with(roots, Arc::clone(&self.search), sink)
+    }
+
+    fn run_with(
+        &self,
+        roots: &[PathBuf],
+        search: Arc<SearchOptions>,
+        sink: impl Sink + Send + 'static,
+    ) -> Result<Stats, Error> {
+        let diagnostics = Arc::new(Diagnostics::new(self.no_messages));
+        let results = Arc::new(AtomicUsize::new(0));
+        let sink = CountingSink {
+            sink: SortedSink {
+                sink,
+                pending: self.sorted.then(Vec::new),
+            },
+            results: Arc::clone(&results),
+        };
+        let job = Job {
+            matcher: Arc::clone(&self.matcher),
+            walker: Walker::new(roots, &self.walk, Arc::clone(&diagnostics))?
+                .cancel_on(self.cancel.clone())
+                .narrow_to(self.candidates.clone()),
+            search,
+            unordered: self.unordered,
+            budget: Arc::new(Budget::new(self.memory_budget)),
+            diagnostics: Arc::clone(&diagnostics),
+            cancel: self.cancel.clone(),
+            max_total: self.max_total,
+            threads: self
+                .threads
+                .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
+        };
+
+        match self.kind {
+            Kind::SelfMade => backend::self_made::run(job, sink),
+            Kind::Rayon => backend::rayon_pool::run(job, sink)?,
+            Kind::Tokio => backend::tokio_runtime::run(job, sink)?,
+        }
+        Ok(Stats {
+            results: results.load(Ordering::SeqCst),
+            errors: diagnostics.errors(),
+        })
+    }
+
+    /// Search `roots` like `run`, then keep watching them: files that are created or grow
+    /// are searched again and only their new matches are handed to `sink`, numbered on
+    /// from the initial search. For a file that was appended to, only the new tail is
+    /// searched. Only returns when watching the file system fails or the search is
+    /// cancelled.
+    pub fn watch(&self, roots: &[PathBuf], sink: impl Sink + Send + 'static) -> Result<(), Error> {
+        // Watch first, so that what is written during the initial search isn't missed.
+        let watching = Watching::start(roots)?;
+        let searched = Arc::new(Searched::default());
+        let search = Arc::new(SearchOptions {
+            searched: Some(Arc::clone(&searched)),
+            ..(*self.search).clone()
+        });
+        let sink = Arc::new(Mutex::new(sink));
+        let stats = self.run_with(roots, search, SharedSink(Arc::clone(&sink)))?;
+
+        let watch = Watch {
+            matcher: &*self.matcher,
+            walk: &self.walk,
+            search: &self.search,
+            diagnostics: Arc::new(Diagnostics::new(self.no_messages)),
+            cancel: &self.cancel,
+        };
+        let mut sink = sink.lock().unwrap_or_else(|e| e.into_inner());
+        watch.run(watching, searched.take(), stats.results, &mut *sink)
+    }
+
+    /// Search `roots` on a background thread and iterate over the results as they come in.
+    /// Dropping the iterator cancels the search, and with it the token from `cancel_token`.
+    pub fn iter(self, roots: &[PathBuf]) -> Result<Results, Error> {
+        // Fail early on bad options rather than on the background thread.
+        Walker::new(roots, &self.walk, Arc::default())?;
+
+        let roots = roots.to_vec();
+        let (tx, rx) = mpsc::sync_channel(ITER_CHANNEL_CAPACITY);
+        thread::spawn(move || {
+            let sink = ChannelSink {
+                tx,
+                cancel: self.cancel.clone(),
+            };
+            let _ = self.run(&roots, sink);
+        });
+        Ok(Results { rx })
+    }
+}
+
+impl SearcherBuilder {
+    /// Which files to search.
+    pub fn walk_options(mut self, options: WalkOptions) -> Self {
+        self.searcher.walk = options;
+        self
+    }
+
+    /// How to search every file.
+    pub fn search_options(mut self, options: SearchOptions) -> Self {
+        self.searcher.search = Arc::new(options);
+        self
+    }
+
+    /// Which concurrency backend to search with.
+    pub fn kind(mut self, kind: Kind) -> Self {
+        self.searcher.kind = kind;
+        self
+    }
+
+    /// How many files to search at the same time, on every `Kind`. Defaults to the
+    /// number of cores.
+    pub fn threads(mut self, threads: Option<usize>) -> Self {
+        self.searcher.threads = threads.map(|n| n.max(1));
+        self
+    }
+
+    /// Hand out results as soon as they are found instead of in order.
+    pub fn unordered(mut self, unordered: bool) -> Self {
+        self.searcher.unordered = unordered;
+        self
+    }
+
+    /// How many bytes of results may wait to be consumed before the workers pause.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.searcher.memory_budget = bytes;
+        self
+    }
+
+    /// Hand out the results sorted by path once the whole search is done, numbered in that
+    /// order. The results are then the same on every run and for every `Kind`.
+    pub fn sorted(mut self, sorted: bool) -> Self {
+        self.searcher.sorted = sorted;
+        self
+    }
+
+    /// Stop the search once `cancel` is set, for example from a Ctrl-C handler.
+    /// A cancelled token stays cancelled, so it stops every later search too.
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.searcher.cancel = cancel;
+        self
+    }
+
+    /// Stop the search once this many matching lines were handed to the sink.
+    /// A file without matching lines, as with `files_without_match`, counts as one.
+    pub fn max_total(mut self, max_total: Option<usize>) -> Self {
+        self.searcher.max_total = max_total;
+        self
+    }
+
+    /// Only search the files that `candidates` can't rule out, from an index built with
+    /// `Index::build`. Results are the same as without, files that changed since the
+    /// index was built are always searched.
+    pub fn candidates(mut self, candidates: Option<Candidates>) -> Self {
+        self.searcher.candidates = candidates.map(Arc::new);
+        self
+    }
+
+    /// Don't print errors about single files and directories on stderr. They are still counted.
+    pub fn no_messages(mut self, no_messages: bool) -> Self {
+        self.searcher.no_messages = no_messages;
+        self
+    }
+
+    pub fn build(mut self) -> Searcher {
+        Arc::make_mut(&mut self.searcher.search).cancel = self.searcher.cancel.clone();
+        self.searcher
+    }
+}
+
+/// The results of `Searcher::iter`, in `search_ctr` order.
+pub struct Results {
+    rx: mpsc::Receiver<GrepResult>,
+}
+
+impl Iterator for Results {
+    type Item = GrepResult;
+
+    fn next(&mut self) -> Option<GrepResult> {
+        self.rx.recv().ok()
+    }
+}
+
+/// Passes results on to another sink and counts them.
+struct CountingSink<S> {
+    sink: S,
+    results: Arc<AtomicUsize>,
+}
+
+impl<S: Sink> Sink for CountingSink<S> {
+    fn result(&mut self, result: GrepResult) {
+        self.results.fetch_add(1, Ordering::SeqCst);
+        self.sink.result(result);
+    }
+
+    fn finish(&mut self) {
+        self.sink.finish();
+    }
+}
+
+/// Passes results on to another sink, or with `pending` set, holds on to them until
+/// the end and passes them on sorted by path.
+struct SortedSink<S> {
+    sink: S,
+    pending: Option<Vec<GrepResult>>,
+}
+
+impl<S: Sink> Sink for SortedSink<S> {
+    fn result(&mut self, result: GrepResult) {
+        match &mut self.pending {
+            Some(pending) => pending.push(result),
+            None => self.sink.result(result),
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut pending) = self.pending.take() {
+            pending.sort_by(|a, b| a.path.cmp(&b.path));
+            for (search_ctr, mut result) in pending.into_iter().enumerate() {
+                result.search_ctr = search_ctr;
+                self.sink.result(result);
+            }
+        }
+        self.sink.finish();
+    }
+}
+
+/// Hands results to a sink that is also used after the search. Doesn't pass `finish` on,
+/// since more results follow.
+struct SharedSink<S>(Arc<Mutex<S>>);
+
+impl<S: Sink> Sink for SharedSink<S> {
+    fn result(&mut self, result: GrepResult) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .result(result);
+    }
+}
+
+/// Sends results to `Results`. Once that is dropped, nobody wants the rest of the results,
+/// so the search is cancelled.
+struct ChannelSink {
+    tx: mpsc::SyncSender<GrepResult>,
+    cancel: CancelToken,
+}
+
+impl Sink for ChannelSink {
+    fn result(&mut self, result: GrepResult) {
+        if self.tx.send(result).is_err() {
+            self.cancel.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::bytes::Regex;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn dropping_results_cancels_the_search() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..2 * ITER_CHANNEL_CAPACITY {
+            std::fs::write(dir.path().join(format!("{:04}.txt", i)), "hit\n").unwrap();
+        }
+        let hit = Regex::new("hit").unwrap();
+        for kind in [Kind::SelfMade, Kind::Rayon, Kind::Tokio] {
+            let cancel = CancelToken::new();
+            let searcher = Searcher::builder(hit.clone())
+                .kind(kind)
+                .cancel_token(cancel.clone())
+                .build();
+            let mut results = searcher.iter(&[dir.path().to_path_buf()]).unwrap();
+            assert!(results.next().is_some());
+            drop(results);
+
+            let start = Instant::now();
+            while !cancel.is_cancelled() {
+                assert!(start.elapsed() < Duration::from_secs(10), "{:?}", kind);
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use ignore::overrides::OverrideBuilder;
+use ignore::types::TypesBuilder;
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::cancel::CancelToken;
+use crate::diagnostics::Diagnostics;
+use crate::index::Candidates;
+use crate::search;
+
+/// Options that decide which files the walker hands to the search backends.
+/// Every `Kind` goes through the same `Walker`, so they all filter identically.
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    /// Also search hidden files and directories (names starting with a dot)
+    pub hidden: bool,
+
+    /// Don't respect `.gitignore`, `.ignore` and the global git excludes
+    pub no_ignore: bool,
+
+    /// Only search files matching one of these globs
+    pub globs: Vec<String>,
+
+    /// Never search files matching one of these globs
+    pub excludes: Vec<String>,
+
+    /// Only search files of these types (`-t rust`)
+    pub types: Vec<String>,
+
+    /// Never search files of these types (`-T js`)
+    pub types_not: Vec<String>,
+
+    /// Extra type definitions of the form `name:glob`, added to the built-in table
+    pub type_defs: Vec<String>,
+
+    /// Skip files larger than this many bytes
+    pub max_filesize: Option<u64>,
+
+    /// Skip files last modified before this time
+    pub newer_than: Option<SystemTime>,
+
+    /// Don't descend more than this many directories below a root
+    pub max_depth: Option<usize>,
+
+    /// Follow symbolic links. Links that lead back to one of their own ancestor
+    /// directories (same device and inode) are reported as loops and not descended into.
+    pub follow: bool,
+
+    /// Don't descend into directories on a different file system than their root
+    pub one_file_system: bool,
+}
+
+/// A configured, single-use directory walker over a set of root paths.
+/// The root `-` stands for standard input and is handed out before anything else.
+pub struct Walker {
+    builder: Option<WalkBuilder>,
+    stdin: bool,
+    diagnostics: Arc<Diagnostics>,
+    cancel: CancelToken,
+    candidates: Option<Arc<Candidates>>,
+}
+
+impl Walker {
+    /// Build a walker for `roots` that reports errors met during the walk to `diagnostics`.
+    /// Fails if one of the glob patterns or type definitions is invalid.
+    pub fn new(
+        roots: &[PathBuf],
+        options: &WalkOptions,
+        diagnostics: Arc<Diagnostics>,
+    ) -> Result<Self, ignore::Error> {
+        let stdin = roots.iter().any(|r| search::is_stdin(r));
+        let mut roots = roots.iter().filter(|r| !search::is_stdin(r));
+        let Some(first) = roots.next() else {
+            return Ok(Self {
+                builder: None,
+                stdin,
+                diagnostics,
+                cancel: CancelToken::new(),
+                candidates: None,
+            });
+        };
+        let mut builder = WalkBuilder::new(first);
+        for root in roots {
+            builder.add(root);
+        }
+
+        // Globs are matched relative to the working directory, like `--glob` in grep tools.
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let mut overrides = OverrideBuilder::new(cwd);
+        for glob in &options.globs {
+            overrides.add(glob)?;
+        }
+        for glob in &options.excludes {
+            overrides.add(&format!("!{}", glob))?;
+        }
+
+        builder
+            .overrides(overrides.build()?)
+            .types(file_types(options)?.build()?)
+            .max_filesize(options.max_filesize)
+            .max_depth(options.max_depth)
+            .hidden(!options.hidden)
+            .ignore(!options.no_ignore)
+            .git_ignore(!options.no_ignore)
+            .git_global(!options.no_ignore)
+            .git_exclude(!options.no_ignore)
+            .parents(!options.no_ignore)
+            // Honor `.gitignore` files even when the tree is not inside a git repository.
+            .require_git(false)
+            .follow_links(options.follow)
+            .same_file_system(options.one_file_system);
+        // Decided from the directory entry's metadata, so skipped files are never opened.
+        if let Some(cutoff) = options.newer_than {
+            builder.filter_entry(move |entry| {
+                entry.file_type().is_some_and(|t| t.is_dir())
+                    || entry
+                        .metadata()
+                        .and_then(|m| Ok(m.modified()?))
+                        .is_ok_and(|modified| modified >= cutoff)
+            });
+        }
+
+        Ok(Self {
+            builder: Some(builder),
+            stdin,
+            diagnostics,
+            cancel: CancelToken::new(),
+            candidates: None,
+        })
+    }
+
+    /// Stop the walk as soon as `cancel` is set.
+    pub fn cancel_on(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Skip the files that `candidates` rules out, as if they had been filtered.
+    pub fn narrow_to(mut self, candidates: Option<Arc<Candidates>>) -> Self {
+        self.candidates = candidates;
+        self
+    }
+
+    /// Walk the tree on the current thread, calling `f` for every file that passes the filters.
+    pub fn for_each(self, mut f: impl FnMut(PathBuf)) {
+        if self.stdin {
+            f(PathBuf::from(search::STDIN));
+        }
+        let Some(builder) = self.builder else {
+            return;
+        };
+        for entry in builder.build() {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            if let Some(entry) = checked(entry, &self.diagnostics, self.candidates.as_deref()) {
+                f(entry.into_path());
+            }
+        }
+    }
+
+    /// Walk the tree on a pool of threads that steal directory reads from each other, calling
+    /// a visitor for every file as soon as it is found, so searching can start right away.
+    /// `make_visitor` is called once for every walker thread.
+    pub fn for_each_parallel<'s, F>(self, mut make_visitor: impl FnMut() -> F)
+    where
+        F: FnMut(PathBuf) + Send + 's,
+    {
+        if self.stdin {
+            make_visitor()(PathBuf::from(search::STDIN));
+        }
+        let Some(builder) = self.builder else {
+            return;
+        };
+        builder.build_parallel().run(|| {
+            let mut visit = make_visitor();
+            let diagnostics = Arc::clone(&self.diagnostics);
+            let cancel = self.cancel.clone();
+            let candidates = self.candidates.clone();
+            Box::new(move |entry| {
+                if cancel.is_cancelled() {
+                    return WalkState::Quit;
+                }
+                if let Some(entry) = checked(entry, &diagnostics, candidates.as_deref()) {
+                    visit(entry.into_path());
+                }
+                WalkState::Continue
+            })
+        });
+    }
+
+    /// Walk the tree to completion and return every file that passes the filters, in walk order.
+    pub fn collect(self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        self.for_each(|path| files.push(path));
+        files
+    }
+}
+
+/// The built-in file type table plus the user's definitions, with the `-t`/`-T` selections applied.
+fn file_types(options: &WalkOptions) -> Result<TypesBuilder, ignore::Error> {
+    let mut types = TypesBuilder::new();
+    types.add_defaults();
+    for def in &options.type_defs {
+        types.add_def(def)?;
+    }
+    for name in &options.types {
+        types.select(name);
+    }
+    for name in &options.types_not {
+        types.negate(name);
+    }
+    Ok(types)
+}
+
+/// Every known file type and its globs, sorted by name, as `--type-list` prints them.
+pub fn type_list(options: &WalkOptions) -> Result<Vec<(String, Vec<String>)>, ignore::Error> {
+    let types = file_types(options)?.build()?;
+    Ok(types
+        .definitions()
+        .iter()
+        .map(|d| (d.name().to_string(), d.globs().to_vec()))
+        .collect())
+}
+
+/// Parse a file size such as `512`, `10K`, `4M` or `1G` (powers of 1024) into bytes.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size {:?}, expected e.g. 100K or 4M", s))?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+/// Parse an age such as `90s`, `30m`, `2h`, `7d` or `2w`. A bare number is in seconds.
+pub fn parse_age(s: &str) -> Result<Duration, String> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c),
+        _ => (s, 's'),
+    };
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(format!("unknown unit in {:?}, use s, m, h, d or w", s)),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid age {:?}, expected e.g. 30m or 2d", s))?;
+    n.checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("age {:?} is too large", s))
+}
+
+/// The entry if it should be searched. Errors, such as unreadable directories, broken
+/// links and symlink loops, are reported to `diagnostics` and the walk goes on.
+fn checked(
+    entry: Result<DirEntry, ignore::Error>,
+    diagnostics: &Diagnostics,
+    candidates: Option<&Candidates>,
+) -> Option<DirEntry> {
+    match entry {
+        Ok(entry) => {
+            if let Some(err) = entry.error() {
+                diagnostics.error(err);
+            }
+            (is_searchable(&entry) && may_match(&entry, candidates)).then_some(entry)
+        }
+        Err(err) => {
+            diagnostics.error(err);
+            None
+        }
+    }
+}
+
+/// Whether the index can't rule out a match in the entry. Files that can't be looked at
+/// are searched, so their errors are reported as usual.
+fn may_match(entry: &DirEntry, candidates: Option<&Candidates>) -> bool {
+    candidates.is_none_or(|candidates| match std::fs::metadata(entry.path()) {
+        Ok(metadata) => candidates.may_match(entry.path(), &metadata),
+        Err(_) => true,
+    })
+}
+
+/// Regular files are always searched. Special files such as pipes only when they are named as a root.
+fn is_searchable(entry: &DirEntry) -> bool {
+    match entry.file_type() {
+        Some(t) if t.is_file() => true,
+        Some(t) => entry.depth() == 0 && !t.is_dir(),
+        None => false,
+    }
+}
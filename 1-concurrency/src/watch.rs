@@ -0,0 +1,292 @@
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::archive;
+use crate::cancel::CancelToken;
+use crate::decompress;
+use crate::diagnostics::Diagnostics;
+use crate::encoding;
+use crate::matcher::Matcher;
+use crate::search::{self, Found, SearchOptions, SearchedTo};
+use crate::searcher::Sink;
+use crate::walker::{WalkOptions, Walker};
+use crate::Error;
+
+/// How often the watch loop checks whether it was cancelled.
+const CANCEL_POLL: Duration = Duration::from_millis(200);
+
+/// How long to wait for more events after one came in, so a burst of writes to the
+/// same file is searched once.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Everything the watch loop needs to search files again.
+pub struct Watch<'a> {
+    pub matcher: &'a dyn Matcher,
+    pub walk: &'a WalkOptions,
+    pub search: &'a SearchOptions,
+    pub diagnostics: Arc<Diagnostics>,
+    pub cancel: &'a CancelToken,
+}
+
+/// The roots being watched, with the events that came in since watching started.
+pub struct Watching {
+    _watcher: RecommendedWatcher,
+    rx: mpsc::Receiver<notify::Result<Event>>,
+    /// The canonical path of every root, with the root as it was given
+    renames: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Watching {
+    /// Start watching `roots`. Events are kept until `Watch::run` gets to them, so nothing
+    /// that changes while the initial search runs is missed.
+    pub fn start(roots: &[PathBuf]) -> Result<Self, Error> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        // Events name absolute paths, results should use the roots as they were given.
+        let mut renames = Vec::new();
+        for root in roots.iter().filter(|r| !search::is_stdin(r)) {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+            renames.push((root.canonicalize()?, root.clone()));
+        }
+        Ok(Self {
+            _watcher: watcher,
+            rx,
+            renames,
+        })
+    }
+}
+
+/// How far a file has been searched already.
+#[derive(Debug, Clone, Copy)]
+struct Tail {
+    /// The size of the file when it was last searched
+    len: u64,
+
+    /// The offset and 0-based number of the last line that was searched
+    last_line: (u64, usize),
+}
+
+impl Tail {
+    /// A file that hasn't been searched at all.
+    const EMPTY: Tail = Tail {
+        len: 0,
+        last_line: (0, 0),
+    };
+
+    /// Search what was appended to `path` since the last time. The last line that was
+    /// searched is searched again, since it may have been incomplete. A file that shrank
+    /// was rewritten and is searched from the start.
+    fn search(
+        &mut self,
+        matcher: &dyn Matcher,
+        path: &Path,
+        options: &SearchOptions,
+    ) -> io::Result<Option<Found>> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        if len < self.len {
+            *self = Tail::EMPTY;
+        }
+        if len == self.len {
+            return Ok(None);
+        }
+
+        let (from, first_line) = self.last_line;
+        file.seek(SeekFrom::Start(from))?;
+        let mut content = Vec::new();
+        file.take(len - from).read_to_end(&mut content)?;
+
+        // Start at the line that was being written when the file was last searched.
+        let start = search::line_start(&content, (self.len - from) as usize);
+        let line = first_line + search::count_lines(&content[..start]);
+        let found = search::search_slice(matcher, &content[start..], options).map(|mut found| {
+            found.shift(from as usize + start, line);
+            found
+        });
+
+        let last = search::line_start(&content, content.len());
+        self.last_line = (
+            from + last as u64,
+            first_line + search::count_lines(&content[..last]),
+        );
+        self.len = from + content.len() as u64;
+        Ok(found)
+    }
+}
+
+impl Watch<'_> {
+    /// Hand the new matches in the files that `watching` reports as created or modified
+    /// to `sink`, numbered from `search_ctr` on. The files in `searched` are only searched
+    /// from where the initial search left off. Only returns when watching fails or
+    /// `cancel` is set.
+    pub fn run(
+        &self,
+        watching: Watching,
+        searched: HashMap<PathBuf, SearchedTo>,
+        mut search_ctr: usize,
+        sink: &mut impl Sink,
+    ) -> Result<(), Error> {
+        let Watching { rx, renames, .. } = &watching;
+        let mut tails: HashMap<PathBuf, Tail> = searched
+            .into_iter()
+            .map(|(path, to)| {
+                let tail = Tail {
+                    len: to.len,
+                    last_line: to.last_line,
+                };
+                (path, tail)
+            })
+            .collect();
+
+        while !self.cancel.is_cancelled() {
+            let event = match rx.recv_timeout(CANCEL_POLL) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            let mut changed = BTreeSet::new();
+            collect(event, &mut changed, &self.diagnostics);
+            while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+                collect(event, &mut changed, &self.diagnostics);
+            }
+            let changed: BTreeSet<_> = changed
+                .into_iter()
+                .map(|path| {
+                    renames
+                        .iter()
+                        .find_map(|(absolute, root)| {
+                            Some(root.join(path.strip_prefix(absolute).ok()?))
+                        })
+                        .unwrap_or(path)
+                })
+                .collect();
+
+            let new: Vec<_> = changed.iter().filter(|p| !tails.contains_key(*p)).collect();
+            let accepted = self.accepted(&new, renames);
+            for path in changed {
+                let tail = match tails.get_mut(&path) {
+                    Some(tail) => tail,
+                    None if accepted.contains(&path) => {
+                        tails.entry(path.clone()).or_insert(Tail::EMPTY)
+                    }
+                    None => continue,
+                };
+                let mut report = |path: PathBuf, found: io::Result<Option<Found>>| match found {
+                    Ok(Some(found)) => {
+                        sink.result(found.into_result(path, search_ctr));
+                        search_ctr += 1;
+                    }
+                    Ok(None) => {}
+                    Err(e) => self.diagnostics.file_error(&path, e),
+                };
+                if (self.search.decompress && decompress::Format::of(&path).is_some())
+                    || (self.search.search_zip && archive::Format::of(&path).is_some())
+                    || is_transcoded(&path, self.search)
+                {
+                    // A compressed stream, transcoded text or an archive can't be searched
+                    // from the middle. Every entry of an archive is searched again.
+                    search::search_each(self.matcher, path, self.search, report);
+                } else {
+                    let found = tail.search(self.matcher, &path, self.search);
+                    report(path, found);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The ones among `paths`, which the initial search didn't see, that pass the walker's
+    /// filters, found by walking the directories they are in. `--max-depth` is counted from
+    /// the root in `renames` that a path is under, like the initial search did.
+    fn accepted(&self, paths: &[&PathBuf], renames: &[(PathBuf, PathBuf)]) -> HashSet<PathBuf> {
+        let dirs: BTreeSet<_> = paths
+            .iter()
+            .filter(|p| within_depth(p, renames, self.walk.max_depth))
+            .filter_map(|p| p.parent())
+            .map(Path::to_path_buf)
+            .collect();
+        if dirs.is_empty() {
+            return HashSet::new();
+        }
+        let options = WalkOptions {
+            max_depth: Some(1),
+            ..self.walk.clone()
+        };
+        let dirs: Vec<_> = dirs.into_iter().collect();
+        match Walker::new(&dirs, &options, Arc::clone(&self.diagnostics)) {
+            Ok(walker) => walker.collect().into_iter().collect(),
+            Err(e) => {
+                self.diagnostics.error(e);
+                HashSet::new()
+            }
+        }
+    }
+}
+
+/// Whether `path` is at most `max_depth` levels below one of the roots in `renames`.
+fn within_depth(path: &Path, renames: &[(PathBuf, PathBuf)], max_depth: Option<usize>) -> bool {
+    max_depth.is_none_or(|max| {
+        renames.iter().any(|(_, root)| {
+            path.strip_prefix(root)
+                .is_ok_and(|relative| relative.components().count() <= max)
+        })
+    })
+}
+
+/// Whether `path` starts like a file that is transcoded before it is searched.
+fn is_transcoded(path: &Path, options: &SearchOptions) -> bool {
+    let mut head = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(encoding::SNIFF_LEN as u64).read_to_end(&mut head))
+        .is_ok()
+        && options.encoding.of(&head).is_some()
+}
+
+/// Add the paths of the files that `event` says were created or written to `changed`.
+fn collect(
+    event: notify::Result<Event>,
+    changed: &mut BTreeSet<PathBuf>,
+    diagnostics: &Diagnostics,
+) {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => return diagnostics.error(e),
+    };
+    match event.kind {
+        EventKind::Create(_)
+        | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any) => {
+            changed.extend(event.paths.into_iter().filter(|p| p.is_file()));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_is_counted_from_the_root() {
+        let renames = [(PathBuf::from("/abs/wd"), PathBuf::from("wd"))];
+        let depth = |path: &str, max| within_depth(Path::new(path), &renames, max);
+        assert!(depth("wd/new.log", Some(1)));
+        assert!(!depth("wd/a/new.log", Some(1)));
+        assert!(!depth("wd/a/b/new.log", Some(1)));
+        assert!(depth("wd/a/new.log", Some(2)));
+        assert!(depth("wd/a/b/new.log", None));
+        assert!(!depth("elsewhere/new.log", Some(3)));
+    }
+
+    #[test]
+    fn depth_of_a_relative_root() {
+        let renames = [(PathBuf::from("/abs"), PathBuf::from("."))];
+        assert!(within_depth(Path::new("./new.log"), &renames, Some(1)));
+        assert!(!within_depth(Path::new("./a/new.log"), &renames, Some(1)));
+    }
+}
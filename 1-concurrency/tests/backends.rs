@@ -0,0 +1,113 @@
+//! Every `--kind` has to print exactly the same bytes for the same search.
+
+mod common;
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+const KINDS: [&str; 3] = ["self-made", "rayon", "tokio"];
+
+fn mygrep(kind: &str, args: &[&str], root: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mygrep"))
+        .args(["--kind", kind, "--color", "never"])
+        .args(args)
+        .arg(common::NEEDLE)
+        .arg(root)
+        .output()
+        .expect("failed to run mygrep")
+}
+
+/// Run every argument set with every backend and compare the results to the first backend's.
+/// The results are sorted by path, so they have to be the same byte for byte.
+fn assert_identical(root: &Path, arg_sets: &[&[&str]]) {
+    for args in arg_sets {
+        let args = &[&["--sort"], *args].concat();
+        let expected = mygrep(KINDS[0], args, root);
+        assert_eq!(
+            expected.status.code(),
+            Some(0),
+            "{:?}: {:?}",
+            args,
+            expected
+        );
+        assert!(!expected.stdout.is_empty(), "{:?}: no output", args);
+        for kind in &KINDS[1..] {
+            let actual = mygrep(kind, args, root);
+            assert_eq!(actual.status.code(), Some(0), "{} {:?}", kind, args);
+            assert!(
+                actual.stdout == expected.stdout,
+                "{} and {} differ for {:?}",
+                KINDS[0],
+                kind,
+                args
+            );
+        }
+    }
+}
+
+#[test]
+fn many_small_files() {
+    let tree = common::many_small(400, 2 * 1024).unwrap();
+    assert_identical(
+        tree.path(),
+        &[
+            &[],
+            &["-n", "-C", "2"],
+            &["--column"],
+            &["--json"],
+            &["-c"],
+            &["-l"],
+            &["-r", "found_$0"],
+        ],
+    );
+}
+
+#[test]
+fn few_huge_files() {
+    let tree = common::few_huge(2, 17 * 1024 * 1024).unwrap();
+    // Large enough to be memory mapped.
+    assert!(tree.bytes > 2 * 16 * 1024 * 1024);
+    assert_identical(tree.path(), &[&["-c"], &["--json"]]);
+}
+
+#[test]
+fn deep_nesting() {
+    let tree = common::deep(40, 3, 1024).unwrap();
+    assert_identical(tree.path(), &[&[], &["-n", "-A", "1"], &["-l"]]);
+}
+
+/// The blocks of default output, one per file, with the `search_ctr` left out of their
+/// headers. Checks that the headers count up from 0 in the order they are printed.
+fn blocks(stdout: &[u8]) -> Vec<String> {
+    let stdout = String::from_utf8_lossy(stdout);
+    let mut blocks = Vec::new();
+    for (search_ctr, block) in stdout.split(">>> (#").skip(1).enumerate() {
+        let (ctr, rest) = block.split_once(") ").expect("malformed header");
+        assert_eq!(ctr.parse(), Ok(search_ctr), "results out of order");
+        blocks.push(rest.to_string());
+    }
+    blocks
+}
+
+/// Without `--sort`, which file gets which `search_ctr` depends on timing, but every
+/// backend has to print results in order and the same results.
+#[test]
+fn ordered_output() {
+    let tree = common::many_small(400, 2 * 1024).unwrap();
+    for args in [&[][..], &["-n", "-C", "1"], &["-r", "found_$0"]] {
+        let mut expected = blocks(&mygrep(KINDS[0], args, tree.path()).stdout);
+        assert!(!expected.is_empty(), "{:?}: no output", args);
+        expected.sort();
+        for kind in &KINDS[1..] {
+            let mut actual = blocks(&mygrep(kind, args, tree.path()).stdout);
+            actual.sort();
+            assert!(
+                actual == expected,
+                "{} and {} differ for {:?}",
+                KINDS[0],
+                kind,
+                args
+            );
+        }
+    }
+}
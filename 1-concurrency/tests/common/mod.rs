@@ -0,0 +1,97 @@
+//! Synthetic source trees, shared by the integration tests and the benchmarks.
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use tempfile::TempDir;
+
+/// The pattern the generated trees are searched for. It occurs on roughly one line in 16.
+pub const NEEDLE: &str = r"needle_\d+";
+
+/// A generated tree in a temporary directory that is removed on drop.
+pub struct Tree {
+    pub dir: TempDir,
+
+    /// The total size of all files in the tree
+    pub bytes: u64,
+}
+
+impl Tree {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// `files` files of about `size` bytes each in a single directory.
+pub fn many_small(files: usize, size: usize) -> io::Result<Tree> {
+    let dir = tempfile::tempdir()?;
+    let mut bytes = 0;
+    for i in 0..files {
+        bytes += write_file(
+            &dir.path().join(format!("file_{:05}.rs", i)),
+            size,
+            i as u64,
+        )?;
+    }
+    Ok(Tree { dir, bytes })
+}
+
+/// `files` files of `size` bytes each.
+pub fn few_huge(files: usize, size: usize) -> io::Result<Tree> {
+    let dir = tempfile::tempdir()?;
+    let mut bytes = 0;
+    for i in 0..files {
+        bytes += write_file(&dir.path().join(format!("huge_{}.log", i)), size, i as u64)?;
+    }
+    Ok(Tree { dir, bytes })
+}
+
+/// A chain of `depth` nested directories with `files` files of about `size` bytes at every level.
+pub fn deep(depth: usize, files: usize, size: usize) -> io::Result<Tree> {
+    let root = tempfile::tempdir()?;
+    let mut dir = root.path().to_path_buf();
+    let mut bytes = 0;
+    for level in 0..depth {
+        dir.push(format!("level_{}", level));
+        fs::create_dir(&dir)?;
+        for i in 0..files {
+            let seed = (level * files + i) as u64;
+            bytes += write_file(&dir.join(format!("file_{}.txt", i)), size, seed)?;
+        }
+    }
+    Ok(Tree { dir: root, bytes })
+}
+
+/// Write about `size` bytes of code-like lines to `path`, the same for the same `seed`.
+fn write_file(path: &Path, size: usize, seed: u64) -> io::Result<u64> {
+    const WORDS: [&str; 8] = [
+        "let", "value", "match", "self", "return", "data", "fn", "index",
+    ];
+
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    let mut state = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    let mut written = 0;
+    while written < size {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let r = state >> 33;
+        let line = if r.is_multiple_of(16) {
+            format!("    {} = needle_{};\n", WORDS[(r % 8) as usize], r % 1000)
+        } else {
+            format!(
+                "    {} {}_{} {};\n",
+                WORDS[(r % 8) as usize],
+                WORDS[(r / 8 % 8) as usize],
+                r % 97,
+                WORDS[(r / 64 % 8) as usize]
+            )
+        };
+        out.write_all(line.as_bytes())?;
+        written += line.len();
+    }
+    out.flush()?;
+    Ok(written as u64)
+}
@@ -0,0 +1,79 @@
+//! The literal prefilter may only skip work: with and without it, every pattern has to
+//! match exactly the same ranges.
+
+use mygrep::matcher::{self, MatchOptions};
+use std::ops::Range;
+
+const HAYSTACKS: [&str; 5] = [
+    "xfoo\nfoo bar\nbar foo\n",
+    "fn main() {\n    let index_12 fn = 3;\n  fn  helper_x(a: u8)\n}\n",
+    "Bar foo\r\nbar FOO\r\n\r\nfoo bar foo",
+    "no literal here\nfoofoo foo\n 42 bar\n42bar 7 bar baz\n",
+    "ärger foo\nÄRGER FOO\nstraße foo bar\n",
+];
+
+const PATTERNS: [&str; 14] = [
+    r"\w+ foo$",
+    r"^\w+ bar",
+    r"[a-z]+ index_\d+ fn",
+    r"^\s*fn\s+\w+",
+    r"\d+ bar\b",
+    r"(?:a|b)ar foo",
+    r".*foo",
+    r"[[:alpha:]]+ foo bar$",
+    r"\bfoo\b.*\bbar\b",
+    r"\s+foo$",
+    r"\w+er foo",
+    r"[^\n]{2}bar",
+    r"\w+ foo\z",
+    r"\w+ foo\r$",
+];
+
+fn ranges(pattern: &str, haystack: &str, options: MatchOptions) -> Vec<Range<usize>> {
+    let matcher = matcher::build(&[pattern.to_string()], &options).unwrap();
+    matcher.find_iter(haystack.as_bytes()).collect()
+}
+
+#[test]
+fn prefilter_never_changes_matches() {
+    let option_sets = [
+        MatchOptions::default(),
+        MatchOptions {
+            word: true,
+            ..MatchOptions::default()
+        },
+        MatchOptions {
+            line: true,
+            ..MatchOptions::default()
+        },
+        MatchOptions {
+            ignore_case: true,
+            ..MatchOptions::default()
+        },
+        MatchOptions {
+            smart_case: true,
+            word: true,
+            ..MatchOptions::default()
+        },
+    ];
+    for options in option_sets {
+        for pattern in PATTERNS {
+            for haystack in HAYSTACKS {
+                let expected = ranges(
+                    pattern,
+                    haystack,
+                    MatchOptions {
+                        no_prefilter: true,
+                        ..options
+                    },
+                );
+                let actual = ranges(pattern, haystack, options);
+                assert_eq!(
+                    actual, expected,
+                    "{:?} in {:?} with {:?}",
+                    pattern, haystack, options
+                );
+            }
+        }
+    }
+}